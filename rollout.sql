@@ -29,3 +29,99 @@ CREATE TABLE timeline_events (
 );
 
 insert into public.timeline_events (month, event, logged_by, year) VALUES('Hammer', 'its hammer time!', 'Odo', '1494 DR');
+
+DROP TABLE IF EXISTS saved_queue_tracks;
+DROP TABLE IF EXISTS saved_queues;
+
+CREATE TABLE saved_queues (
+    guild_id BIGINT PRIMARY KEY,
+    voice_channel_id BIGINT not NULL,
+    text_channel_id BIGINT not NULL,
+    saved_at TIMESTAMP default now()
+);
+
+CREATE TABLE saved_queue_tracks (
+    guild_id BIGINT not NULL REFERENCES saved_queues (guild_id) ON DELETE CASCADE,
+    position integer not NULL,
+    url text not NULL,
+    requester_id BIGINT NULL,
+    offset_ms BIGINT default 0 not NULL,
+    PRIMARY KEY (guild_id, position)
+);
+
+DROP TABLE IF EXISTS play_history;
+
+CREATE TABLE play_history (
+    id SERIAL PRIMARY KEY,
+    guild_id BIGINT not NULL,
+    url text not NULL,
+    title text not NULL,
+    requester_id BIGINT NULL,
+    played_at TIMESTAMPTZ default now() not NULL,
+    duration_ms BIGINT NULL,
+    listened_ms BIGINT default 0 not NULL,
+    failed BOOLEAN default FALSE not NULL
+);
+
+CREATE INDEX play_history_guild_played_at ON play_history (guild_id, played_at DESC);
+
+DROP TABLE IF EXISTS playlist_tracks;
+DROP TABLE IF EXISTS playlists;
+
+CREATE TABLE playlists (
+    id SERIAL PRIMARY KEY,
+    guild_id BIGINT not NULL,
+    owner_id BIGINT NULL,
+    name text not NULL,
+    created_at TIMESTAMPTZ default now() not NULL
+);
+
+CREATE UNIQUE INDEX playlists_guild_owner_name ON playlists (guild_id, coalesce(owner_id, 0), lower(name));
+
+CREATE TABLE playlist_tracks (
+    id SERIAL PRIMARY KEY,
+    playlist_id INTEGER not NULL REFERENCES playlists (id) ON DELETE CASCADE,
+    position INTEGER not NULL,
+    url text not NULL,
+    title text not NULL,
+    duration_ms BIGINT NULL
+);
+
+CREATE INDEX playlist_tracks_playlist_position ON playlist_tracks (playlist_id, position);
+
+DROP TABLE IF EXISTS library_tracks;
+
+CREATE TABLE library_tracks (
+    id SERIAL PRIMARY KEY,
+    path text UNIQUE not NULL,
+    title text not NULL,
+    artist text NULL,
+    album text NULL,
+    duration_ms BIGINT NULL,
+    scanned_at TIMESTAMPTZ default now() not NULL
+);
+
+DROP TABLE IF EXISTS loudness_measurements;
+
+CREATE TABLE loudness_measurements (
+    url text PRIMARY KEY,
+    integrated DOUBLE PRECISION not NULL,
+    true_peak DOUBLE PRECISION not NULL,
+    range DOUBLE PRECISION not NULL,
+    threshold DOUBLE PRECISION not NULL,
+    measured_at TIMESTAMPTZ default now() not NULL
+);
+
+DROP TABLE IF EXISTS scenes;
+
+CREATE TABLE scenes (
+    id SERIAL PRIMARY KEY,
+    guild_id BIGINT not NULL,
+    name text not NULL,
+    playlist text NULL,
+    shuffle BOOLEAN default FALSE not NULL,
+    ambience text NULL,
+    volume SMALLINT default 100 not NULL
+);
+
+CREATE UNIQUE INDEX scenes_guild_name ON scenes (guild_id, lower(name));
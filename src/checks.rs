@@ -1,3 +1,5 @@
+use std::env;
+
 use serenity::{
     client::Context,
     framework::standard::{macros::check, Args, CommandOptions, Reason},
@@ -5,8 +7,12 @@ use serenity::{
     prelude::Mentionable,
 };
 use tracing::{span, Instrument, Level};
+use uuid::Uuid;
 
-use crate::utils::SunnyError;
+use crate::{
+    structs::with_guild_state,
+    utils::{SunnyError, SunnyResult},
+};
 
 #[check]
 #[name = "In_Voice"]
@@ -18,16 +24,23 @@ pub async fn in_same_voice_check(
     _args: &mut Args,
     _command_options: &CommandOptions,
 ) -> Result<(), Reason> {
-    let span = span!(Level::INFO, "in_same_voice_check", ?msg);
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("Guild ID Empty"))?;
+
+    in_same_voice(ctx, guild_id, msg.author.id).await?;
+    Ok(())
+}
+
+/// What the `In_Voice` check checks, for button presses that don't go
+/// through the framework
+pub async fn in_same_voice(ctx: &Context, guild_id: GuildId, user_id: UserId) -> SunnyResult<()> {
+    let span = span!(Level::INFO, "in_same_voice", ?guild_id, ?user_id);
     async move {
         let songbird = songbird::get(ctx)
             .await
             .ok_or_else(|| SunnyError::log("Failed to get songbird"))?;
 
-        let guild_id = msg
-            .guild_id
-            .ok_or_else(|| SunnyError::log("Guild ID Empty"))?;
-
         let channel = {
             let songbird_call_m = songbird
                 .get(guild_id)
@@ -42,8 +55,9 @@ pub async fn in_same_voice_check(
 
         let name = ChannelId(channel.0);
 
-        let guild = msg
-            .guild(&ctx.cache)
+        let guild = ctx
+            .cache
+            .guild(guild_id)
             .await
             .ok_or_else(|| SunnyError::log("Couldn't get guild"))?;
 
@@ -51,10 +65,10 @@ pub async fn in_same_voice_check(
 
         states
             .any(|vs| match vs.channel_id {
-                Some(c_id) => channel.0 == c_id.0 && vs.user_id.0 == msg.author.id.0,
+                Some(c_id) => channel.0 == c_id.0 && vs.user_id.0 == user_id.0,
                 None => false,
             })
-            .then(|| ())
+            .then_some(())
             .ok_or_else(|| {
                 SunnyError::user(
                     format!("I only take requests from users in {}", name.mention()).as_str(),
@@ -65,3 +79,89 @@ pub async fn in_same_voice_check(
     .instrument(span)
     .await
 }
+
+/// Whether a member has the role named by `role_var` (`default` if it isn't
+/// set) or the Manage Server permission.
+async fn has_role_or_manager(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    role_var: &str,
+    default: &str,
+) -> SunnyResult<bool> {
+    let guild = ctx
+        .cache
+        .guild(guild_id)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get guild"))?;
+
+    let role_name = env::var(role_var).unwrap_or_else(|_| default.to_string());
+
+    let member = guild
+        .member(ctx, user_id)
+        .await
+        .map_err(|e| SunnyError::log(format!("Couldn't get member: {}", e).as_str()))?;
+
+    if let Some(role) = guild.role_by_name(&role_name) {
+        if member.roles.contains(&role.id) {
+            return Ok(true);
+        }
+    }
+
+    let permissions = guild
+        .member_permissions(ctx, user_id)
+        .await
+        .map_err(|e| SunnyError::log(format!("Couldn't get permissions: {}", e).as_str()))?;
+
+    Ok(permissions.manage_guild())
+}
+
+/// Whether a member may manage everyone's tracks, either through the role named
+/// by `DJ_ROLE` ("DJ" by default) or the Manage Server permission.
+pub async fn is_dj(ctx: &Context, guild_id: GuildId, user_id: UserId) -> SunnyResult<bool> {
+    has_role_or_manager(ctx, guild_id, user_id, "DJ_ROLE", "DJ").await
+}
+
+/// Whether a member runs the game and may manage its sounds, either through
+/// the role named by `DM_ROLE` ("DM" by default) or the Manage Server permission.
+pub async fn is_dm(ctx: &Context, guild_id: GuildId, user_id: UserId) -> SunnyResult<bool> {
+    has_role_or_manager(ctx, guild_id, user_id, "DM_ROLE", "DM").await
+}
+
+/// Whether a member may skip the current song without a vote: its requester
+/// or a DJ can.
+pub async fn can_force_skip(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> SunnyResult<bool> {
+    let current = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?
+        .lock()
+        .await
+        .queue()
+        .current()
+        .ok_or_else(|| SunnyError::user("No song playing"))?;
+
+    can_manage_track(ctx, guild_id, user_id, current.uuid()).await
+}
+
+/// Whether a member may manage a queued track: only its requester or a DJ can.
+pub async fn can_manage_track(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    uuid: Uuid,
+) -> SunnyResult<bool> {
+    let requester =
+        with_guild_state(ctx, guild_id, |s| s.records.get(&uuid).map(|r| r.requester)).await?;
+
+    if requester == Some(user_id) {
+        return Ok(true);
+    }
+
+    is_dj(ctx, guild_id, user_id).await
+}
@@ -1,4 +1,4 @@
-use std::{env, collections::HashSet, num::NonZeroUsize, fmt, fmt::format, str::FromStr};
+use std::{env, collections::HashSet, num::NonZeroUsize, fmt, str::FromStr, time::Duration};
 
 use serenity::{
     client::Context,
@@ -10,23 +10,22 @@ use serenity::{
     model::prelude::*,
 };
 
-use url::Url;
-
 use crate::{
     checks::*,
     effects::{
-        self, display_queue, now_playing,
+        self, display_queue, fade, history, library, loudness, mixer, now_playing, playlist,
+        scene, soundboard,
         queue::{self, EnqueueAt},
     },
-    structs::EventConfig,
+    structs::{with_guild_state, Announce, AutoplaySource, EventConfig, FilterPreset, Layer, TrackRecord},
     utils::SunnyError
 };
 
 
-use tokio_postgres::{NoTls, Error, Config, types::ToSql};
+use tokio_postgres::{NoTls, Config, types::ToSql};
 use futures_util::{pin_mut, TryStreamExt};
 use tracing::{event, Level};
-use sysinfo::{NetworkExt, NetworksExt, ProcessExt, System, SystemExt};
+use sysinfo::{NetworkExt, System, SystemExt};
 
 struct TimelineEvent {
     id: i32,
@@ -92,83 +91,1476 @@ pub async fn join(ctx: &Context, msg: &Message) -> CommandResult {
         .ok_or_else(|| SunnyError::user("Not in a voice"))?;
 
     let bot_id = ctx.cache.current_user_id().await;
-    let same_voice = guild
-        .voice_states
-        .get(&bot_id)
-        .and_then(|vs| vs.channel_id)
-        .map_or(false, |id| id == voice_channel_id);
+    let same_voice =
+        guild.voice_states.get(&bot_id).and_then(|vs| vs.channel_id) == Some(voice_channel_id);
+
+    if same_voice {
+        return Err(SunnyError::user("Already in that voice channel!").into());
+    }
+
+    let call_m = effects::join(&EventConfig {
+        ctx: ctx.clone(),
+        guild_id: guild.id,
+        text_channel_id: msg.channel_id,
+        voice_channel_id,
+    })
+    .await?;
+
+    effects::deafen(call_m).await;
+    msg.channel_id
+        .say(&ctx.http, format!("Joined {}", voice_channel_id.mention()))
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(In_Voice)]
+/// Removes Sunny from the current voice channel and clears the queue.
+pub async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild = msg
+        .guild(&ctx.cache)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get guild"))?;
+
+    effects::leave(ctx, guild.id).await?;
+
+    msg.reply(&ctx.http, "Left voice").await?;
+
+    Ok(())
+}
+
+fn validate_url(mut args: Args) -> Option<String> {
+    effects::parse_url(&args.single::<String>().ok()?)
+}
+
+/// Parses an optional `--at <position>` in front of the remaining arguments
+fn parse_at(args: &mut Args) -> Result<Option<usize>, SunnyError> {
+    if args.current() != Some("--at") {
+        return Ok(None);
+    }
+
+    args.advance();
+
+    args.single::<usize>()
+        .map(Some)
+        .map_err(|_| SunnyError::user("Expected a position after `--at`"))
+}
+
+#[command]
+#[aliases(p)]
+#[max_args(3)]
+#[only_in(guilds)]
+#[usage("[--at <position>] <url>")]
+#[example("https://www.youtube.com/watch?v=dQw4w9WgXcQ")]
+#[example("--at 3 https://www.youtube.com/watch?v=dQw4w9WgXcQ")]
+#[checks(In_Voice)]
+/// While Sunny is in a voice channel, you may run the play command so that she
+/// can start streaming the given video URL. Use `--at` to put it at a specific
+/// position in the queue.
+pub async fn play(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let at = parse_at(&mut args)?;
+
+    let url = validate_url(args).ok_or_else(|| SunnyError::user("Unable to parse url"))?;
+
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let position = queue::play(
+        ctx,
+        guild_id,
+        url,
+        at.map_or(EnqueueAt::Back, EnqueueAt::Index),
+        TrackRecord::new(msg.author.id, "play"),
+    )
+    .await?;
+
+    let reply = if position == 0 {
+        "Started playing the song".to_string()
+    } else {
+        match effects::wait_time(ctx, guild_id, position).await? {
+            Some(wait) => format!(
+                "Added song to queue: position {}, plays in {}",
+                position,
+                effects::format_wait(wait)
+            ),
+            None => format!("Added song to queue: position {}", position),
+        }
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[aliases(pn)]
+#[max_args(1)]
+#[only_in(guilds)]
+#[usage("<url>")]
+#[example("https://www.youtube.com/watch?v=dQw4w9WgXcQ")]
+#[checks(In_Voice)]
+/// While Sunny is in a voice channel, you may run the play command so that she
+/// can start streaming the given video URL.
+pub async fn play_next(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let url = validate_url(args).ok_or_else(|| SunnyError::user("Unable to parse url"))?;
+
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    queue::play(
+        ctx,
+        guild_id,
+        url,
+        EnqueueAt::Front,
+        TrackRecord::new(msg.author.id, "play_next"),
+    )
+    .await?;
+
+    msg.reply(&ctx.http, "Added song to front of queue").await?;
+
+    Ok(())
+}
+
+/// Parses an optional `--seed <n>` in front of the remaining arguments
+fn parse_seed(args: &mut Args) -> Result<Option<u64>, SunnyError> {
+    if args.current() != Some("--seed") {
+        return Ok(None);
+    }
+
+    args.advance();
+
+    args.single::<u64>()
+        .map(Some)
+        .map_err(|_| SunnyError::user("Expected a number after `--seed`"))
+}
+
+#[command]
+#[only_in(guilds)]
+#[max_args(2)]
+#[sub_commands(shuffle_continuous)]
+#[usage("[--seed <n>]")]
+#[example("--seed 42")]
+/// Shuffles the upcoming songs, `unshuffle` puts them back in order. The same
+/// seed shuffles the same queue the same way.
+pub async fn shuffle(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let seed = parse_seed(&mut args)?;
+
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("Failed to get guild id"))?;
+
+    let seed = queue::shuffle(ctx, guild_id, seed).await?;
+    msg.reply(
+        &ctx.http,
+        format!("Queue Shuffled :game_die:! Seed `{}`", seed),
+    )
+    .await?;
+    Ok(())
+}
+
+#[command("continuous")]
+#[aliases(always)]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("[on|off]")]
+#[example("on")]
+/// Toggles continuous shuffle, where every song added goes to a random
+/// position in the queue instead of the end
+pub async fn shuffle_continuous(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let current = with_guild_state(ctx, guild_id, |s| s.continuous_shuffle).await?;
+    let enabled = parse_toggle(&mut args, current)?;
+
+    let reply = match queue::set_continuous(ctx, guild_id, enabled).await? {
+        Some(seed) => format!(
+            "Continuous shuffle on: new songs go anywhere in the queue :game_die: Seed `{}`",
+            seed
+        ),
+        None => "Continuous shuffle off: new songs go to the end of the queue".to_string(),
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+/// Puts the queue back in the order it had before it was shuffled, and stops
+/// continuous shuffle
+pub async fn unshuffle(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    queue::unshuffle(ctx, guild_id).await?;
+    msg.reply(&ctx.http, "Queue unshuffled").await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("[on|off]")]
+#[example("on")]
+#[checks(In_Voice)]
+/// Toggles fair queue mode, where requesters take turns instead of songs being
+/// played in the order they were added
+pub async fn fair(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let current = with_guild_state(ctx, guild_id, |s| s.fair_queue).await?;
+    let enabled = parse_toggle(&mut args, current)?;
+
+    queue::set_fair(ctx, guild_id, enabled).await?;
+
+    let reply = if enabled {
+        "Fair queue on: everyone takes turns :handshake:"
+    } else {
+        "Fair queue off: first come, first served"
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(radio)]
+#[max_args(3)]
+#[usage("<history|library|playlist [--mine] <name>|off>")]
+#[example("history")]
+#[example("playlist tavern")]
+#[checks(In_Voice)]
+/// Keeps the music going once the queue runs out, picking songs people
+/// listened to before, songs from a playlist or from the local library. Queueing a song takes over
+/// from autoplay right away.
+pub async fn autoplay(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let source = if args.is_empty() {
+        match with_guild_state(ctx, guild_id, |s| s.autoplay.clone()).await? {
+            Some(_) => None,
+            None => Some(AutoplaySource::History),
+        }
+    } else {
+        match args.single::<String>()?.to_lowercase().as_str() {
+            "history" | "on" => Some(AutoplaySource::History),
+            "library" => Some(AutoplaySource::Library),
+            "playlist" => {
+                let (owner, name) = parse_playlist(&mut args, msg.author.id)?;
+
+                // Make sure it exists before relying on it
+                playlist::tracks(guild_id, owner, &name).await?;
+
+                Some(AutoplaySource::Playlist { owner, name })
+            }
+            "off" => None,
+            _ => {
+                return Err(SunnyError::user(
+                    "Expected `history`, `library`, `playlist <name>` or `off`",
+                )
+                .into())
+            }
+        }
+    };
+
+    let reply = match &source {
+        Some(AutoplaySource::History) => {
+            "Autoplay on: playing from the history once the queue runs out :radio:".to_string()
+        }
+        Some(AutoplaySource::Playlist { owner, name }) => format!(
+            "Autoplay on: playing from {} once the queue runs out :radio:",
+            playlist::describe(name, *owner)
+        ),
+        Some(AutoplaySource::Library) => {
+            "Autoplay on: playing from the local library once the queue runs out :radio:"
+                .to_string()
+        }
+        None => "Autoplay off".to_string(),
+    };
+
+    effects::set_autoplay(ctx, guild_id, source).await?;
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(fade)]
+#[num_args(1)]
+#[usage("<seconds|off>")]
+#[example("4")]
+#[example("off")]
+#[checks(In_Voice)]
+/// Overlaps the end of each song with the start of the next one, and fades
+/// out on `stop`, `skip` and `leave`
+pub async fn crossfade(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let arg = args.single::<String>()?;
+
+    let crossfade = if arg.eq_ignore_ascii_case("off") {
+        None
+    } else {
+        let secs = arg
+            .parse::<f64>()
+            .ok()
+            .filter(|s| (0.0..=fade::MAX_CROSSFADE.as_secs_f64()).contains(s))
+            .ok_or_else(|| {
+                SunnyError::user(
+                    format!(
+                        "Expected up to {} seconds or `off`",
+                        fade::MAX_CROSSFADE.as_secs()
+                    )
+                    .as_str(),
+                )
+            })?;
+
+        Some(Duration::from_secs_f64(secs)).filter(|d| !d.is_zero())
+    };
+
+    fade::set_crossfade(ctx, guild_id, crossfade).await?;
+
+    let reply = match crossfade {
+        Some(d) => format!("Crossfading songs over {:.1}s :ocean:", d.as_secs_f64()),
+        None => "Crossfade off".to_string(),
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(normalize)]
+#[max_args(1)]
+#[usage("[<target LUFS>|on|off]")]
+#[example("-14")]
+#[example("off")]
+#[checks(In_Voice)]
+/// Brings every song to the same loudness, -16 LUFS unless you pick a
+/// target. Without arguments it's toggled.
+pub async fn loudness(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let target = if args.is_empty() {
+        let audio = with_guild_state(ctx, guild_id, |s| s.audio.clone()).await?;
+        let on = matches!(audio.read(), Ok(a) if a.loudness_target.is_some());
+
+        (!on).then_some(loudness::DEFAULT_TARGET)
+    } else {
+        match args.single::<String>()?.to_lowercase().as_str() {
+            "on" => Some(loudness::DEFAULT_TARGET),
+            "off" => None,
+            target => {
+                let target = target
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|t| loudness::TARGET_RANGE.contains(t))
+                    .ok_or_else(|| {
+                        SunnyError::user("Expected a target between -70 and -5 LUFS, `on` or `off`")
+                    })?;
+
+                Some(target)
+            }
+        }
+    };
+
+    loudness::set_loudness(ctx, guild_id, target).await?;
+
+    let reply = match target {
+        Some(t) => format!("Normalizing songs to {} LUFS :level_slider:", t),
+        None => "Loudness normalization off".to_string(),
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(fx)]
+#[num_args(1)]
+#[usage("<bassboost|nightcore|vaporwave|muffled|echo|normalize|8d|off>")]
+#[example("nightcore")]
+#[example("off")]
+#[checks(In_Voice)]
+/// Applies an audio filter to the current and queued songs
+pub async fn filter(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let name = args.single::<String>()?;
+
+    let filter = if name.eq_ignore_ascii_case("off") {
+        None
+    } else {
+        let filter = FilterPreset::parse(&name).ok_or_else(|| {
+            let names: Vec<&str> = FilterPreset::ALL.iter().map(|f| f.name()).collect();
+
+            SunnyError::user(format!("Expected one of `{}` or `off`", names.join("`, `")).as_str())
+        })?;
+
+        Some(filter)
+    };
+
+    effects::set_filter(ctx, guild_id, filter).await?;
+
+    let reply = match filter {
+        Some(f) => format!("Filter set to **{}** :control_knobs:", f.name()),
+        None => "Filter off".to_string(),
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+/// Parses `[--mine] <name>`, personal playlists belong to `user` while the
+/// others belong to the whole server
+fn parse_playlist(args: &mut Args, user: UserId) -> Result<(Option<UserId>, String), SunnyError> {
+    let owner = if args.current() == Some("--mine") {
+        args.advance();
+        Some(user)
+    } else {
+        None
+    };
+
+    let name = args
+        .single_quoted::<String>()
+        .map_err(|_| SunnyError::user("Missing playlist name"))?;
+
+    Ok((owner, name))
+}
+
+/// Only DJs can change the server's playlists, personal ones are up to their owner
+async fn can_edit_playlist(
+    ctx: &Context,
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    user: UserId,
+) -> CommandResult {
+    if owner.is_none() && !is_dj(ctx, guild_id, user).await? {
+        return Err(SunnyError::user(
+            "Only DJs can change the server's playlists, use `--mine` for your own",
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(pl)]
+#[sub_commands(
+    playlist_save,
+    playlist_load,
+    playlist_add,
+    playlist_remove,
+    playlist_list,
+    playlist_show
+)]
+/// Saved playlists, for the whole server or just for you with `--mine`. Lists
+/// the playlists when used without a subcommand.
+pub async fn playlist(ctx: &Context, msg: &Message) -> CommandResult {
+    playlist_list(ctx, msg, Args::new("", &[])).await
+}
+
+#[command("save")]
+#[only_in(guilds)]
+#[min_args(1)]
+#[max_args(2)]
+#[usage("[--mine] <name>")]
+#[example("tavern")]
+#[example("--mine \"boss fight\"")]
+#[checks(In_Voice)]
+/// Saves the current queue as a playlist, replacing it if it already exists
+pub async fn playlist_save(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let (owner, name) = parse_playlist(&mut args, msg.author.id)?;
+    can_edit_playlist(ctx, guild_id, owner, msg.author.id).await?;
+
+    let saved = playlist::save(ctx, guild_id, owner, &name).await?;
+
+    msg.reply(
+        &ctx.http,
+        format!(
+            "Saved {} songs to {} :floppy_disk:",
+            saved,
+            playlist::describe(&name, owner)
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[command("load")]
+#[only_in(guilds)]
+#[min_args(1)]
+#[max_args(2)]
+#[usage("[--mine] <name>")]
+#[example("tavern")]
+#[checks(In_Voice)]
+/// Adds every song of a playlist to the queue
+pub async fn playlist_load(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let (owner, name) = parse_playlist(&mut args, msg.author.id)?;
+
+    let loaded = playlist::load(ctx, guild_id, owner, &name, msg.author.id).await?;
+
+    msg.reply(
+        &ctx.http,
+        format!(
+            "Added {} songs from {} to the queue",
+            loaded,
+            playlist::describe(&name, owner)
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[command("add")]
+#[only_in(guilds)]
+#[min_args(2)]
+#[max_args(3)]
+#[usage("[--mine] <name> <url>")]
+#[example("tavern https://www.youtube.com/watch?v=dQw4w9WgXcQ")]
+/// Adds a song to the end of a playlist, creating the playlist if needed
+pub async fn playlist_add(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let (owner, name) = parse_playlist(&mut args, msg.author.id)?;
+    can_edit_playlist(ctx, guild_id, owner, msg.author.id).await?;
+
+    let url = validate_url(args).ok_or_else(|| SunnyError::user("Unable to parse url"))?;
+
+    let title = playlist::add(guild_id, owner, &name, url).await?;
+
+    msg.reply(
+        &ctx.http,
+        format!("Added {} to {}", title, playlist::describe(&name, owner)),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[command("remove")]
+#[only_in(guilds)]
+#[min_args(2)]
+#[max_args(3)]
+#[usage("[--mine] <name> <position>")]
+#[example("tavern 3")]
+/// Removes a song from a playlist, see `playlist show` for the positions
+pub async fn playlist_remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let (owner, name) = parse_playlist(&mut args, msg.author.id)?;
+    can_edit_playlist(ctx, guild_id, owner, msg.author.id).await?;
+
+    let position = args
+        .single::<usize>()
+        .map_err(|_| SunnyError::user("Position must be a number"))?;
+
+    let title = playlist::remove(guild_id, owner, &name, position).await?;
+
+    msg.reply(
+        &ctx.http,
+        format!(
+            "Removed {} from {}",
+            title,
+            playlist::describe(&name, owner)
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[command("list")]
+#[only_in(guilds)]
+/// Lists the server's playlists and your own
+pub async fn playlist_list(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let playlists = playlist::list(guild_id, msg.author.id).await?;
+
+    let lines: Vec<String> = playlists
+        .iter()
+        .map(|p| {
+            let mine = if p.owner.is_some() { " (yours)" } else { "" };
+            format!("**{}**{} - {} songs", p.name, mine, p.tracks)
+        })
+        .collect();
+
+    let description = if lines.is_empty() {
+        "No playlists yet, save one with `playlist save <name>`".to_string()
+    } else {
+        lines.join("\n")
+    };
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.embed(|e| {
+                e.author(|a| a.name("Playlists"));
+                e.description(description)
+            })
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[command("show")]
+#[only_in(guilds)]
+#[min_args(1)]
+#[max_args(2)]
+#[usage("[--mine] <name>")]
+#[example("tavern")]
+/// Shows the songs in a playlist
+pub async fn playlist_show(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let (owner, name) = parse_playlist(&mut args, msg.author.id)?;
+
+    let tracks = playlist::tracks(guild_id, owner, &name).await?;
+    let embed = playlist::generate_embed(&name, owner, &tracks);
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| m.set_embed(embed))
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[min_args(2)]
+#[max_args(2)]
+#[usage("<position> <position>")]
+#[example("4 2")]
+/// Swaps two songs in the queue by their number
+pub async fn swap(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("Failed to get guild id"))?;
+
+    let a = args
+        .single::<NonZeroUsize>()
+        .map_err(|_| SunnyError::user("Invalid arguments"))?;
+
+    let b = args
+        .single::<NonZeroUsize>()
+        .map_err(|_| SunnyError::user("Invalid arguments"))?;
+
+    let (t1, t2) = queue::swap(ctx, guild_id, a.into(), b.into()).await?;
+
+    msg.reply(
+        &ctx.http,
+        format!(
+            "Swapped `{}` and `{}`",
+            effects::get_song(t1.metadata()),
+            effects::get_song(t2.metadata())
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[command("move")]
+#[only_in(guilds)]
+#[aliases(mv)]
+#[min_args(2)]
+#[max_args(2)]
+#[usage("<from> <to>")]
+#[example("5 1")]
+/// Moves a song to another position in the queue, keeping the order of the
+/// other songs
+pub async fn move_track(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("Failed to get guild id"))?;
+
+    let from = args
+        .single::<usize>()
+        .map_err(|_| SunnyError::user("Invalid arguments"))?;
+
+    let to = args
+        .single::<usize>()
+        .map_err(|_| SunnyError::user("Invalid arguments"))?;
+
+    let track = queue::move_to(ctx, guild_id, from, to).await?;
+
+    msg.reply(
+        &ctx.http,
+        format!(
+            "Moved `{}` to position {}",
+            effects::get_song(track.metadata()),
+            to
+        ),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(np)]
+/// Shows the now playing controller, moving it here if it's elsewhere. Its
+/// buttons work like the commands they're named after.
+pub async fn now_playing(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    now_playing::send_embed(ctx, guild_id, msg.channel_id).await?;
+
+    msg.delete(&ctx.http).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("[every|live|silent]")]
+#[example("every")]
+#[example("silent")]
+#[checks(In_Voice)]
+/// How songs are announced: a new message for every song, one live message
+/// edited as songs change, or nothing until someone uses `now_playing`
+pub async fn announce(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    if args.is_empty() {
+        let announce = with_guild_state(ctx, guild_id, |s| s.announce).await?;
+        msg.reply(
+            &ctx.http,
+            format!("Announcing songs: `{}`", announce.name()),
+        )
+        .await?;
+
+        return Ok(());
+    }
+
+    let announce = Announce::parse(&args.single::<String>()?)
+        .ok_or_else(|| SunnyError::user("Expected `every`, `live` or `silent`"))?;
+
+    now_playing::set_announce(ctx, guild_id, announce).await?;
+
+    let reply = match announce {
+        Announce::Every => "Announcing every song :loudspeaker:",
+        Announce::Live => "Announcing songs in one live message",
+        Announce::Silent => "Not announcing songs, `now_playing` still shows them",
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("[on|off]")]
+#[example("on")]
+/// Whether Sunny sends you a DM when a song you queued starts playing
+pub async fn notify(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let user_id = msg.author.id;
+
+    if args.is_empty() {
+        let on = with_guild_state(ctx, guild_id, |s| s.start_dms.contains(&user_id)).await?;
+        let reply = if on {
+            "You get a DM when your songs start"
+        } else {
+            "You don't get DMs when your songs start"
+        };
+        msg.reply(&ctx.http, reply).await?;
+
+        return Ok(());
+    }
+
+    let on = match args.single::<String>()?.to_lowercase().as_str() {
+        "on" => true,
+        "off" => false,
+        _ => return Err(SunnyError::user("Expected `on` or `off`").into()),
+    };
+
+    with_guild_state(ctx, guild_id, |s| {
+        if on {
+            s.start_dms.insert(user_id);
+        } else {
+            s.start_dms.remove(&user_id);
+        }
+    })
+    .await?;
+
+    let reply = if on {
+        "You'll get a DM when your songs start :bell:"
+    } else {
+        "You won't get DMs when your songs start"
+    };
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(In_Voice)]
+/// Pauses the currently playing
+pub async fn pause(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    queue::pause(ctx, guild_id).await?;
+
+    msg.reply(&ctx.http, "Track paused").await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(In_Voice)]
+/// Resumes the current song if it was paused
+pub async fn resume(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    queue::resume(ctx, guild_id).await?;
+
+    msg.reply(&ctx.http, "Track resumed").await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(In_Voice)]
+/// Skips the currently playing song and starts the next song in the queue.
+/// With vote skipping on, listeners vote unless you requested the song or are a DJ.
+pub async fn skip(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let len = match effects::skip_or_vote(ctx, guild_id, msg.channel_id, msg.author.id).await? {
+        Some(len) => len,
+        None => return Ok(()),
+    };
+
+    msg.reply(
+        &ctx.http,
+        format!("Song skipped: {} in queue.", len.saturating_sub(1)),
+    )
+    .await?;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("<percentage|off>")]
+#[example("50")]
+/// Sets the percentage of listeners needed to vote skip a song, or turns
+/// voting off so skips are instant. Only DJs can change this.
+pub async fn vote_skip(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    if !is_dj(ctx, guild_id, msg.author.id).await? {
+        return Err(SunnyError::user("Only DJs can change vote skipping").into());
+    }
+
+    let arg = args
+        .single::<String>()
+        .map_err(|_| SunnyError::user("Expected a percentage or `off`"))?;
+
+    let fraction = if arg.eq_ignore_ascii_case("off") {
+        None
+    } else {
+        let percentage = arg
+            .trim_end_matches('%')
+            .parse::<f32>()
+            .ok()
+            .filter(|p| *p > 0.0 && *p <= 100.0)
+            .ok_or_else(|| SunnyError::user("The percentage has to be between 1 and 100"))?;
+
+        Some(percentage / 100.0)
+    };
+
+    with_guild_state(ctx, guild_id, |s| s.vote_skip = fraction).await?;
+
+    let reply = match fraction {
+        Some(f) => format!("Skipping now needs {:.0}% of listeners to vote", f * 100.0),
+        None => "Vote skipping off, skips are instant".to_string(),
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(In_Voice)]
+/// Stops playing the current song and clears the current song queue.
+pub async fn stop(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    queue::stop(ctx, guild_id).await?;
+
+    msg.reply(&ctx.http, "Queue cleared, autoplay off.").await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(q, queueueueu)]
+#[sub_commands(queue_export, queue_import)]
+/// Shows the current queue, songs picked from its menu can be removed or moved
+pub async fn queue(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    display_queue::send_embed(ctx, guild_id, msg.channel_id).await?;
+    Ok(())
+}
+
+/// Largest queue file `queue import` accepts
+const MAX_QUEUE_FILE: u64 = 1024 * 1024;
+
+#[command("export")]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("[m3u|json]")]
+#[example("json")]
+/// Attaches the current queue as an `.m3u` (default) or JSON file
+pub async fn queue_export(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let format = if args.is_empty() {
+        effects::QueueFormat::M3u
+    } else {
+        effects::QueueFormat::parse(&args.single::<String>()?)
+            .ok_or_else(|| SunnyError::user("Expected `m3u` or `json`"))?
+    };
+
+    let contents = effects::export_queue(ctx, guild_id, format).await?;
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.content("Here's the queue :floppy_disk:");
+            m.add_file((contents.as_slice(), format.file_name()))
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[command("import")]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("[m3u|json] (with the file attached)")]
+#[checks(In_Voice)]
+/// Adds the songs of an attached `.m3u` or JSON queue file to the queue, like
+/// the ones made by `queue export`
+pub async fn queue_import(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let attachment = msg
+        .attachments
+        .first()
+        .ok_or_else(|| SunnyError::user("Attach a queue file to import"))?;
+
+    let format = if args.is_empty() {
+        attachment
+            .filename
+            .rsplit_once('.')
+            .and_then(|(_, ext)| effects::QueueFormat::parse(ext))
+            .ok_or_else(|| SunnyError::user("Unknown file type, expected `.m3u` or `.json`"))?
+    } else {
+        effects::QueueFormat::parse(&args.single::<String>()?)
+            .ok_or_else(|| SunnyError::user("Expected `m3u` or `json`"))?
+    };
+
+    if attachment.size > MAX_QUEUE_FILE {
+        return Err(SunnyError::user("That file is too big for a queue").into());
+    }
+
+    let contents = attachment.download().await.map_err(|e| {
+        SunnyError::user_and_log(
+            "Couldn't download the attachment",
+            format!("Failed to download attachment: {}", e).as_str(),
+        )
+    })?;
+
+    let (imported, total) =
+        effects::import_queue(ctx, guild_id, &contents, format, msg.author.id).await?;
+
+    let reply = if imported == total {
+        format!("Imported {} songs", imported)
+    } else {
+        format!(
+            "Imported {} of {} songs, the others couldn't be loaded",
+            imported, total
+        )
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+/// Parses a `<from>-<to>` range of queue positions
+fn parse_range(arg: &str) -> Option<(usize, usize)> {
+    let (from, to) = arg.split_once('-')?;
+
+    Some((from.trim().parse().ok()?, to.trim().parse().ok()?))
+}
+
+fn removed_reply(removed: usize) -> String {
+    match removed {
+        0 => "Nothing to remove...".to_string(),
+        1 => "Removed 1 song".to_string(),
+        n => format!("Removed {} songs", n),
+    }
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(r, remove)]
+#[max_args(1)]
+#[example("2")]
+#[example("3-8")]
+#[example("@Sunny")]
+#[usage("<position|from-to|@user>")]
+/// Removes a song from the queue by its position, a range of songs, or all songs
+/// requested by someone. Only the requester or a DJ may remove someone's song
+pub async fn remove_at(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    if let Some(user) = msg.mentions.first() {
+        if user.id != msg.author.id && !is_dj(ctx, guild_id, msg.author.id).await? {
+            return Err(SunnyError::user("Only DJs can remove other people's songs").into());
+        }
+
+        let removed = queue::remove_by(ctx, guild_id, user.id).await?;
+        msg.reply(&ctx.http, removed_reply(removed)).await?;
+
+        return Ok(());
+    }
+
+    if let Some((from, to)) = args.current().and_then(parse_range) {
+        let removed = queue::remove_range(ctx, guild_id, from, to, msg.author.id).await?;
+        msg.reply(&ctx.http, removed_reply(removed)).await?;
+
+        return Ok(());
+    }
+
+    #[allow(clippy::unwrap_used)]
+    let index = args
+        .single::<NonZeroUsize>()
+        .unwrap_or_else(|_| NonZeroUsize::new(1).unwrap());
+
+    let q = queue::remove_at(ctx, guild_id, index, msg.author.id).await?;
+
+    msg.reply(
+        &ctx.http,
+        format!("Removed: `{}`", effects::get_song(q.metadata())),
+    )
+    .await?;
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[checks(In_Voice)]
+/// Removes songs from the queue that are already in it, only DJs can remove
+/// other people's songs
+pub async fn dedupe(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let removed = queue::dedupe(ctx, guild_id, msg.author.id).await?;
+
+    msg.reply(&ctx.http, removed_reply(removed)).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(clear)]
+#[checks(In_Voice)]
+/// Clears the queue but keeps playing the current song, unlike `stop`. Only
+/// DJs can clear the queue.
+pub async fn clear_upcoming(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    if !is_dj(ctx, guild_id, msg.author.id).await? {
+        return Err(SunnyError::user("Only DJs can clear the queue").into());
+    }
+
+    let removed = queue::clear_upcoming(ctx, guild_id).await?;
+
+    msg.reply(&ctx.http, removed_reply(removed)).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(h)]
+#[max_args(1)]
+#[usage("[page]")]
+#[example("2")]
+/// Shows the songs played in this server, most recent first
+pub async fn history(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let page = if args.is_empty() {
+        1
+    } else {
+        args.single::<i64>()
+            .ok()
+            .filter(|p| *p > 0)
+            .ok_or_else(|| SunnyError::user("Page must be a positive number"))?
+    };
+
+    let (entries, total) = history::page(guild_id, page - 1).await?;
+    let embed = history::generate_embed(&entries, page - 1, total);
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| m.set_embed(embed))
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(previous, prev)]
+#[checks(In_Voice)]
+/// Plays the previous song again
+pub async fn back(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let prev = history::back(ctx, guild_id, msg.author.id).await?;
+
+    msg.reply(&ctx.http, format!("Going back to {} :rewind:", prev.title))
+        .await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[min_args(1)]
+#[max_args(1)]
+#[usage("<history position>")]
+#[example("3")]
+#[checks(In_Voice)]
+/// Adds a song from the `history` back to the queue
+pub async fn replay(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let n = args
+        .single::<usize>()
+        .map_err(|_| SunnyError::user("History position must be a number"))?;
+
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let entry = history::entry(guild_id, n).await?;
+
+    let position = queue::play(
+        ctx,
+        guild_id,
+        entry.url,
+        EnqueueAt::Back,
+        TrackRecord::new(msg.author.id, "replay"),
+    )
+    .await?;
+
+    let reply = if position == 0 {
+        format!("Started playing {}", entry.title)
+    } else {
+        format!("Added {} to queue: position {}", entry.title, position)
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(lib)]
+#[sub_commands(library_search, library_play, library_scan)]
+#[usage("<search|play|scan>")]
+/// The local music library, files on Sunny's host indexed by their tags
+pub async fn library(ctx: &Context, msg: &Message) -> CommandResult {
+    msg.reply(
+        &ctx.http,
+        "Use `library search <text>` to find songs and `library play <id>` to play them",
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[command("search")]
+#[only_in(guilds)]
+#[min_args(1)]
+#[usage("<text>")]
+#[example("tavern")]
+/// Searches the titles, artists, albums and file names in the library
+pub async fn library_search(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    let query = args.rest().trim();
+
+    let tracks = library::search(query).await?;
+    let embed = library::generate_embed(query, &tracks);
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| m.set_embed(embed))
+        .await?;
+
+    Ok(())
+}
+
+#[command("play")]
+#[only_in(guilds)]
+#[min_args(1)]
+#[max_args(1)]
+#[usage("<id>")]
+#[example("12")]
+#[checks(In_Voice)]
+/// Adds a song from the library to the queue, see `library search` for the ids
+pub async fn library_play(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let id = args
+        .single::<i32>()
+        .map_err(|_| SunnyError::user("Library id must be a number"))?;
+
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let track = library::get(id).await?;
+
+    let position = queue::play(
+        ctx,
+        guild_id,
+        track.path,
+        EnqueueAt::Back,
+        TrackRecord::new(msg.author.id, "library"),
+    )
+    .await?;
+
+    let reply = if position == 0 {
+        format!("Started playing {}", track.title)
+    } else {
+        format!("Added {} to queue: position {}", track.title, position)
+    };
+
+    msg.reply(&ctx.http, reply).await?;
+
+    Ok(())
+}
+
+#[command("scan")]
+#[only_in(guilds)]
+/// Rescans the library directory for new, changed and removed files (DJs only)
+pub async fn library_scan(ctx: &Context, msg: &Message) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    if !is_dj(ctx, guild_id, msg.author.id).await? {
+        return Err(SunnyError::user("Only DJs can rescan the library").into());
+    }
+
+    let indexed = library::scan().await?;
+
+    msg.reply(&ctx.http, format!("Indexed {} songs :books:", indexed))
+        .await?;
+
+    Ok(())
+}
+
+/// Parses a layer volume in percent
+fn parse_volume(args: &mut Args) -> Result<f32, SunnyError> {
+    args.single::<u8>()
+        .ok()
+        .filter(|v| *v <= mixer::MAX_VOLUME)
+        .map(|v| f32::from(v) / 100.0)
+        .ok_or_else(|| {
+            SunnyError::user(format!("Volume must be between 0 and {}", mixer::MAX_VOLUME).as_str())
+        })
+}
+
+/// Parses `on` or `off`, toggling `current` without an argument
+fn parse_toggle(args: &mut Args, current: bool) -> Result<bool, SunnyError> {
+    if args.is_empty() {
+        return Ok(!current);
+    }
 
-    if same_voice {
-        return Err(SunnyError::user("Already in that voice channel!").into());
+    match args
+        .single::<String>()
+        .unwrap_or_default()
+        .to_lowercase()
+        .as_str()
+    {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(SunnyError::user("Expected `on` or `off`")),
     }
+}
 
-    let call_m = effects::join(&EventConfig {
-        ctx: ctx.clone(),
-        guild_id: guild.id,
-        text_channel_id: msg.channel_id,
-        voice_channel_id,
-    })
-    .await?;
+async fn layer_volume(ctx: &Context, msg: &Message, mut args: Args, layer: Layer) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    effects::deafen(call_m).await;
-    msg.channel_id
-        .say(&ctx.http, format!("Joined {}", voice_channel_id.mention()))
-        .await?;
+    let volume = parse_volume(&mut args)?;
+    mixer::set_volume(ctx, guild_id, layer, volume).await?;
+
+    msg.reply(
+        &ctx.http,
+        format!(
+            "{} volume set to {}%",
+            layer.name(),
+            (volume * 100.0).round()
+        ),
+    )
+    .await?;
 
     Ok(())
 }
 
-#[command]
-#[only_in(guilds)]
-#[checks(In_Voice)]
-/// Removes Sunny from the current voice channel and clears the queue.
-pub async fn leave(ctx: &Context, msg: &Message) -> CommandResult {
-    let guild = msg
-        .guild(&ctx.cache)
-        .await
-        .ok_or_else(|| SunnyError::log("Couldn't get guild"))?;
+async fn layer_loop(ctx: &Context, msg: &Message, mut args: Args, layer: Layer) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    effects::leave(ctx, guild.id).await?;
+    let current = with_guild_state(ctx, guild_id, |s| s.layer(layer).looping).await?;
+    let looping = parse_toggle(&mut args, current)?;
 
-    msg.reply(&ctx.http, "Left voice").await?;
+    mixer::set_looping(ctx, guild_id, layer, looping).await?;
+
+    let reply = match (layer, looping) {
+        (Layer::Music, true) => "Looping the current song :repeat_one:".to_string(),
+        (Layer::Music, false) => "Not looping the current song anymore".to_string(),
+        (_, true) => format!("Looping {} :repeat:", layer.name()),
+        (_, false) => format!("Not looping {} anymore", layer.name()),
+    };
+
+    msg.reply(&ctx.http, reply).await?;
 
     Ok(())
 }
 
-fn validate_url(mut args: Args) -> Option<String> {
-    let mut url: String = args.single().ok()?;
+async fn layer_stop(ctx: &Context, msg: &Message, layer: Layer) -> CommandResult {
+    let guild_id = msg
+        .guild_id
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    if url.starts_with('<') && url.ends_with('>') {
-        url = url[1..url.len() - 1].to_string();
-    }
+    mixer::stop_layer(ctx, guild_id, layer).await?;
 
-    Url::parse(&url).ok()?;
+    msg.reply(&ctx.http, format!("Stopped {}", layer.name()))
+        .await?;
 
-    Some(url)
+    Ok(())
 }
 
-#[command]
-#[aliases(p)]
-#[max_args(1)]
-#[only_in(guilds)]
-#[usage("<url>")]
-#[example("https://www.youtube.com/watch?v=dQw4w9WgXcQ")]
-#[checks(In_Voice)]
-/// While Sunny is in a voice channel, you may run the play command so that she
-/// can start streaming the given video URL.
-pub async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let url = validate_url(args).ok_or_else(|| SunnyError::user("Unable to parse url"))?;
-
+/// Lists the clips of a guild for `ambience` and `sfx` without arguments
+async fn list_clips(ctx: &Context, msg: &Message, layer: Layer) -> CommandResult {
     let guild_id = msg
         .guild_id
         .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    let len = queue::play(ctx, guild_id, url, EnqueueAt::Back).await?;
+    let names: Vec<String> = mixer::clips(guild_id)?
+        .into_iter()
+        .map(|(name, _)| format!("`{}`", name))
+        .collect();
 
-    let reply = if len == 1 {
-        "Started playing the song".to_string()
+    let reply = if names.is_empty() {
+        "There are no clips yet".to_string()
     } else {
-        format!("Added song to queue: position {}", len - 1)
+        format!(
+            "Play one with `{} <clip>`: {}",
+            layer.name(),
+            names.join(", ")
+        )
     };
 
     msg.reply(&ctx.http, reply).await?;
@@ -177,199 +1569,412 @@ pub async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
 }
 
 #[command]
-#[aliases(pn)]
-#[max_args(1)]
 #[only_in(guilds)]
-#[usage("<url>")]
-#[example("https://www.youtube.com/watch?v=dQw4w9WgXcQ")]
-#[checks(In_Voice)]
-/// While Sunny is in a voice channel, you may run the play command so that she
-/// can start streaming the given video URL.
-pub async fn play_next(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let url = validate_url(args).ok_or_else(|| SunnyError::user("Unable to parse url"))?;
-
+/// Shows the layers of the mixer: music, ambience and sound effects
+pub async fn mixer(ctx: &Context, msg: &Message) -> CommandResult {
     let guild_id = msg
         .guild_id
         .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    queue::play(ctx, guild_id, url, EnqueueAt::Front).await?;
+    let layers = mixer::layers(ctx, guild_id).await?;
+    let embed = mixer::generate_embed(&layers);
 
-    msg.reply(&ctx.http, "Added song to front of queue").await?;
+    msg.channel_id
+        .send_message(&ctx.http, |m| m.set_embed(embed))
+        .await?;
 
     Ok(())
 }
 
 #[command]
 #[only_in(guilds)]
-/// Shuffles your queue badly
-pub async fn shuffle(ctx: &Context, msg: &Message) -> CommandResult {
+#[sub_commands(music_volume, music_loop, music_stop)]
+#[usage("<volume|loop|stop>")]
+/// The music layer of the mixer, that's the queue
+pub async fn music(ctx: &Context, msg: &Message) -> CommandResult {
+    mixer(ctx, msg, Args::new("", &[])).await
+}
+
+#[command("volume")]
+#[only_in(guilds)]
+#[num_args(1)]
+#[usage("<0-100>")]
+#[example("80")]
+#[checks(In_Voice)]
+/// Sets the volume of the music
+pub async fn music_volume(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    layer_volume(ctx, msg, args, Layer::Music).await
+}
+
+#[command("loop")]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("[on|off]")]
+#[checks(In_Voice)]
+/// Loops the current song
+pub async fn music_loop(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    layer_loop(ctx, msg, args, Layer::Music).await
+}
+
+#[command("stop")]
+#[only_in(guilds)]
+#[checks(In_Voice)]
+/// Stops the music and clears the queue, like `stop`
+pub async fn music_stop(ctx: &Context, msg: &Message) -> CommandResult {
+    layer_stop(ctx, msg, Layer::Music).await
+}
+
+#[command]
+#[only_in(guilds)]
+#[aliases(amb)]
+#[sub_commands(ambience_play, ambience_volume, ambience_loop, ambience_stop)]
+#[usage("<play|volume|loop|stop>")]
+/// Background sounds looping under the music, lists the clips without a
+/// subcommand
+pub async fn ambience(ctx: &Context, msg: &Message) -> CommandResult {
+    list_clips(ctx, msg, Layer::Ambience).await
+}
+
+#[command("play")]
+#[only_in(guilds)]
+#[num_args(1)]
+#[usage("<clip>")]
+#[example("rain.ogg")]
+#[checks(In_Voice)]
+/// Plays a clip as the ambience, replacing the one playing
+pub async fn ambience_play(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let guild_id = msg
         .guild_id
-        .ok_or_else(|| SunnyError::log("Failed to get guild id"))?;
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
+
+    let name = args.single_quoted::<String>()?;
+    let (name, _) = mixer::play_clip(ctx, guild_id, Layer::Ambience, &name).await?;
+
+    msg.reply(&ctx.http, format!("Ambience: {} :cloud_rain:", name))
+        .await?;
 
-    queue::shuffle(ctx, guild_id).await?;
-    msg.reply(&ctx.http, "Queue Shuffled :game_die:!").await?;
     Ok(())
 }
 
+#[command("volume")]
+#[only_in(guilds)]
+#[num_args(1)]
+#[usage("<0-100>")]
+#[example("30")]
+#[checks(In_Voice)]
+/// Sets the volume of the ambience
+pub async fn ambience_volume(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    layer_volume(ctx, msg, args, Layer::Ambience).await
+}
+
+#[command("loop")]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("[on|off]")]
+#[checks(In_Voice)]
+/// Whether ambience loops, it does by default
+pub async fn ambience_loop(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    layer_loop(ctx, msg, args, Layer::Ambience).await
+}
+
+#[command("stop")]
+#[only_in(guilds)]
+#[checks(In_Voice)]
+/// Stops the ambience
+pub async fn ambience_stop(ctx: &Context, msg: &Message) -> CommandResult {
+    layer_stop(ctx, msg, Layer::Ambience).await
+}
+
 #[command]
 #[only_in(guilds)]
-#[min_args(2)]
-#[max_args(2)]
-#[usage("<position> <position>")]
-#[example("4 2")]
-/// Swaps two songs in the queue by their number
-pub async fn swap(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+#[sub_commands(sfx_volume, sfx_loop, sfx_stop)]
+#[max_args(1)]
+#[usage("<clip>")]
+#[example("dragon_roar")]
+#[checks(In_Voice)]
+/// Plays a sound effect over the music, lists the clips without arguments
+pub async fn sfx(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let guild_id = msg
         .guild_id
-        .ok_or_else(|| SunnyError::log("Failed to get guild id"))?;
-
-    let a = args
-        .single::<NonZeroUsize>()
-        .map_err(|_| SunnyError::user("Invalid arguments"))?;
+        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    let b = args
-        .single::<NonZeroUsize>()
-        .map_err(|_| SunnyError::user("Invalid arguments"))?;
+    if args.is_empty() {
+        return list_clips(ctx, msg, Layer::Sfx).await;
+    }
 
-    let (t1, t2) = queue::swap(ctx, guild_id, a.into(), b.into()).await?;
+    let name = args.single_quoted::<String>()?;
+    mixer::play_clip(ctx, guild_id, Layer::Sfx, &name).await?;
 
-    msg.reply(
-        &ctx.http,
-        format!(
-            "Swapped `{}` and `{}`",
-            effects::get_song(t1.metadata()),
-            effects::get_song(t2.metadata())
-        ),
-    )
-    .await?;
+    msg.react(&ctx.http, '🔊').await?;
 
     Ok(())
 }
 
+#[command("volume")]
+#[only_in(guilds)]
+#[num_args(1)]
+#[usage("<0-100>")]
+#[example("60")]
+#[checks(In_Voice)]
+/// Sets the volume of sound effects
+pub async fn sfx_volume(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    layer_volume(ctx, msg, args, Layer::Sfx).await
+}
+
+#[command("loop")]
+#[only_in(guilds)]
+#[max_args(1)]
+#[usage("[on|off]")]
+#[checks(In_Voice)]
+/// Whether sound effects loop
+pub async fn sfx_loop(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
+    layer_loop(ctx, msg, args, Layer::Sfx).await
+}
+
+#[command("stop")]
+#[only_in(guilds)]
+#[checks(In_Voice)]
+/// Stops every sound effect
+pub async fn sfx_stop(ctx: &Context, msg: &Message) -> CommandResult {
+    layer_stop(ctx, msg, Layer::Sfx).await
+}
+
 #[command]
 #[only_in(guilds)]
-#[aliases(np)]
-/// Shows the currently playing media
-pub async fn now_playing(ctx: &Context, msg: &Message) -> CommandResult {
+#[aliases(sb)]
+#[sub_commands(soundboard_add, soundboard_remove)]
+/// Sends a soundboard, every clip gets a button that plays it over the music
+pub async fn soundboard(ctx: &Context, msg: &Message) -> CommandResult {
     let guild_id = msg
         .guild_id
         .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    now_playing::send_embed(ctx, guild_id, msg.channel_id).await?;
-
-    msg.delete(&ctx.http).await?;
+    soundboard::send_embed(ctx, guild_id, msg.channel_id).await?;
 
     Ok(())
 }
 
-#[command]
+#[command("add")]
 #[only_in(guilds)]
-#[checks(In_Voice)]
-/// Pauses the currently playing
-pub async fn pause(ctx: &Context, msg: &Message) -> CommandResult {
+#[max_args(1)]
+#[usage("[name]")]
+#[example("dragon_roar")]
+/// Adds the attached audio file to the soundboard, named after the file
+/// unless a name is given. Only DMs can add clips.
+pub async fn soundboard_add(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let guild_id = msg
         .guild_id
         .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    queue::pause(ctx, guild_id).await?;
+    if !is_dm(ctx, guild_id, msg.author.id).await? {
+        return Err(SunnyError::user("Only DMs can add clips").into());
+    }
 
-    msg.reply(&ctx.http, "Track paused").await?;
+    let attachment = msg
+        .attachments
+        .first()
+        .ok_or_else(|| SunnyError::user("Attach the clip to the message"))?;
+
+    let name = args.single_quoted::<String>().ok();
+    let name = mixer::add_clip(guild_id, name, attachment).await?;
+
+    msg.channel_id
+        .say(&ctx.http, format!("Added `{}` to the soundboard", name))
+        .await?;
 
     Ok(())
 }
 
-#[command]
+#[command("remove")]
 #[only_in(guilds)]
-#[checks(In_Voice)]
-/// Resumes the current song if it was paused
-pub async fn resume(ctx: &Context, msg: &Message) -> CommandResult {
+#[aliases(rm)]
+#[num_args(1)]
+#[usage("<name>")]
+#[example("dragon_roar")]
+/// Removes a clip uploaded in this server from the soundboard. Only DMs can
+/// remove clips.
+pub async fn soundboard_remove(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let guild_id = msg
         .guild_id
         .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    queue::resume(ctx, guild_id).await?;
+    if !is_dm(ctx, guild_id, msg.author.id).await? {
+        return Err(SunnyError::user("Only DMs can remove clips").into());
+    }
 
-    msg.reply(&ctx.http, "Track resumed").await?;
+    let name = args.single_quoted::<String>()?;
+    mixer::remove_clip(guild_id, &name).await?;
+
+    msg.channel_id
+        .say(&ctx.http, format!("Removed `{}` from the soundboard", name))
+        .await?;
 
     Ok(())
 }
 
 #[command]
 #[only_in(guilds)]
+#[sub_commands(scene_list, scene_set, scene_delete)]
+#[max_args(2)]
+#[usage("<name> [fade seconds]")]
+#[example("tavern")]
+#[example("combat 5")]
 #[checks(In_Voice)]
-/// Skips the currently playing song and starts the next song in the queue.
-pub async fn skip(ctx: &Context, msg: &Message) -> CommandResult {
+/// Switches to a scene: its playlist, ambience and music volume at once,
+/// fading into it over the given seconds. Lists the scenes without arguments.
+/// Only DMs and DJs can switch scenes.
+pub async fn scene(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let guild_id = msg
         .guild_id
         .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    let len = queue::skip(ctx, guild_id).await?;
+    if args.is_empty() {
+        return scene_list(ctx, msg, args).await;
+    }
+
+    let user_id = msg.author.id;
+    if !is_dm(ctx, guild_id, user_id).await? && !is_dj(ctx, guild_id, user_id).await? {
+        return Err(SunnyError::user("Only DMs and DJs can switch scenes").into());
+    }
+
+    let name = args.single_quoted::<String>()?;
+
+    let fade = if args.is_empty() {
+        None
+    } else {
+        let secs = args
+            .single::<f64>()
+            .ok()
+            .filter(|s| (0.0..=fade::MAX_CROSSFADE.as_secs_f64()).contains(s))
+            .ok_or_else(|| {
+                SunnyError::user(
+                    format!("Fades are up to {} seconds", fade::MAX_CROSSFADE.as_secs()).as_str(),
+                )
+            })?;
+
+        Some(Duration::from_secs_f64(secs))
+    };
+
+    let scene = scene::apply(ctx, guild_id, &name, fade, msg.author.id).await?;
 
     msg.reply(
         &ctx.http,
-        format!(
-            "Song skipped: {} in queue.",
-            len.checked_sub(1).unwrap_or_default()
-        ),
+        format!("Set the scene: **{}** :performing_arts:", scene.name),
     )
     .await?;
+
     Ok(())
 }
 
-#[command]
+#[command("list")]
 #[only_in(guilds)]
-#[checks(In_Voice)]
-/// Stops playing the current song and clears the current song queue.
-pub async fn stop(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
+/// Lists the server's scenes
+pub async fn scene_list(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
     let guild_id = msg
         .guild_id
         .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    queue::stop(ctx, guild_id).await?;
+    let scenes = scene::list(guild_id).await?;
 
-    msg.reply(&ctx.http, "Queue cleared.").await?;
+    msg.channel_id
+        .send_message(&ctx.http, |m| m.set_embed(scene::generate_embed(&scenes)))
+        .await?;
 
     Ok(())
 }
 
-#[command]
+#[command("set")]
 #[only_in(guilds)]
-#[aliases(q, queueueueu)]
-/// Shows the current queue
-pub async fn queue(ctx: &Context, msg: &Message) -> CommandResult {
+#[min_args(2)]
+#[max_args(3)]
+#[usage("<name> <playlist|shuffle|ambience|volume> <value>")]
+#[example("combat playlist battle")]
+#[example("combat shuffle on")]
+#[example("combat ambience none")]
+#[example("tavern ambience crowd")]
+#[example("tavern volume 50")]
+/// Changes part of a scene, creating the scene if needed. Only DMs can edit
+/// scenes.
+pub async fn scene_set(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let guild_id = msg
         .guild_id
         .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    display_queue::send_embed(ctx, guild_id, msg.channel_id).await?;
+    if !is_dm(ctx, guild_id, msg.author.id).await? {
+        return Err(SunnyError::user("Only DMs can edit scenes").into());
+    }
+
+    let name = args.single_quoted::<String>()?;
+    let mut scene = scene::get(guild_id, &name)
+        .await?
+        .unwrap_or_else(|| scene::Scene::new(name));
+
+    let part = args.single::<String>()?.to_lowercase();
+
+    // `none` clears the playlist or ambience
+    let mut value = || {
+        args.single_quoted::<String>()
+            .ok()
+            .filter(|v| !v.eq_ignore_ascii_case("none"))
+    };
+
+    match part.as_str() {
+        "playlist" => {
+            let playlist = value();
+            if let Some(playlist) = &playlist {
+                playlist::tracks(guild_id, None, playlist).await?;
+            }
+
+            scene.playlist = playlist;
+        }
+        "ambience" => {
+            scene.ambience = match value() {
+                Some(clip) => Some(mixer::find_clip(guild_id, &clip)?.0),
+                None => None,
+            };
+        }
+        "shuffle" => scene.shuffle = parse_toggle(&mut args, scene.shuffle)?,
+        "volume" => scene.volume = parse_volume(&mut args)?,
+        _ => {
+            return Err(
+                SunnyError::user("Expected `playlist`, `shuffle`, `ambience` or `volume`").into(),
+            )
+        }
+    }
+
+    scene::save(guild_id, &scene).await?;
+
+    msg.reply(
+        &ctx.http,
+        format!("**{}** - {}", scene.name, scene::describe(&scene)),
+    )
+    .await?;
+
     Ok(())
 }
 
-#[command]
+#[command("delete")]
 #[only_in(guilds)]
-#[aliases(r, remove)]
-#[max_args(1)]
-#[example("2")]
-#[usage("<position>")]
-/// Removes a song from the queue by its position
-pub async fn remove_at(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+#[aliases(rm)]
+#[num_args(1)]
+#[usage("<name>")]
+#[example("tavern")]
+/// Deletes a scene. Only DMs can delete scenes.
+pub async fn scene_delete(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
     let guild_id = msg
         .guild_id
         .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
 
-    #[allow(clippy::unwrap_used)]
-    let index = args
-        .single::<NonZeroUsize>()
-        .unwrap_or_else(|_| NonZeroUsize::new(1).unwrap());
+    if !is_dm(ctx, guild_id, msg.author.id).await? {
+        return Err(SunnyError::user("Only DMs can delete scenes").into());
+    }
 
-    let q = queue::remove_at(ctx, guild_id, index).await?;
+    let name = args.single_quoted::<String>()?;
+    scene::delete(guild_id, &name).await?;
+
+    msg.reply(&ctx.http, format!("Deleted scene `{}`", name))
+        .await?;
 
-    msg.reply(
-        &ctx.http,
-        format!("Removed: `{}`", effects::get_song(q.metadata())),
-    )
-    .await?;
     Ok(())
 }
 
@@ -383,8 +1988,7 @@ pub async fn ping(ctx: &Context, msg: &Message) -> CommandResult {
 
 fn get_db_pw() -> String {
     event!(Level::INFO, "Attempting to find database pw..");
-    let db_pw = env::var("DB_PW").expect("Environment variable DB_PW not found");
-    return db_pw
+    env::var("DB_PW").expect("Environment variable DB_PW not found")
 }
 
 fn slice_iter<'a>(
@@ -410,8 +2014,7 @@ pub async fn get_group_items(ctx: &Context, msg: &Message) -> CommandResult {
     .password(get_db_pw())
     .dbname("farflungfellowship")
     .connect(NoTls)
-    .await
-    .unwrap();
+    .await?;
     
     // The connection object performs the actual communication with the database,
     // so spawn it off to run on its own.
@@ -423,7 +2026,7 @@ pub async fn get_group_items(ctx: &Context, msg: &Message) -> CommandResult {
     event!(Level::INFO, "Connected to db...");
     msg.channel_id.say(&ctx.http, ":thumbsup: ...database connected for fetching group_items...").await?;
 
-    let mut it = client
+    let it = client
         .query_raw("SELECT id, name, quantity, description, url FROM group_items", slice_iter(&[]))
         .await?;
 
@@ -472,7 +2075,7 @@ pub async fn add_group_item(ctx: &Context, msg: &Message, mut args: Args) -> Com
 
     let url = args.single::<String>().unwrap_or_default(); 
 
-    let to_be_added_msg = format!(":fork_and_knife: ...preparing to add: {} - {} - {} - {}", name, description, result_quantity.to_string(), url);
+    let to_be_added_msg = format!(":fork_and_knife: ...preparing to add: {} - {} - {} - {}", name, description, result_quantity, url);
 
     msg.channel_id.say(&ctx.http, to_be_added_msg).await?;
     
@@ -487,8 +2090,7 @@ pub async fn add_group_item(ctx: &Context, msg: &Message, mut args: Args) -> Com
     .password(get_db_pw())
     .dbname("farflungfellowship")
     .connect(NoTls)
-    .await
-    .unwrap();
+    .await?;
     
     // The connection object performs the actual communication with the database,
     // so spawn it off to run on its own.
@@ -504,7 +2106,7 @@ pub async fn add_group_item(ctx: &Context, msg: &Message, mut args: Args) -> Com
     let item_description = description.trim();
     let item_url = url.trim();
 
-    let mut _it = client
+    let _it = client
         .query("insert into public.group_items (name, description, url, quantity) values($1, $2, $3, $4)", &[&item_name, &item_description, &item_url, &result_quantity])
         .await?;
 
@@ -532,7 +2134,7 @@ pub async fn delete_group_item(ctx: &Context, msg: &Message, mut args: Args) ->
     
     event!(Level::INFO, "Attempting to connect to db...");
     let db_item_id_sanitize = item_id.trim();
-    let db_item = i32::from_str(&db_item_id_sanitize).unwrap_or(1);
+    let db_item = i32::from_str(db_item_id_sanitize).unwrap_or(1);
     msg.channel_id.say(&ctx.http, ":alarm_clock: ...connecting to database to delete the group item...").await?;
 
     let (client, connection) = Config::new()
@@ -542,8 +2144,7 @@ pub async fn delete_group_item(ctx: &Context, msg: &Message, mut args: Args) ->
     .password(get_db_pw())
     .dbname("farflungfellowship")
     .connect(NoTls)
-    .await
-    .unwrap();
+    .await?;
     
     // The connection object performs the actual communication with the database,
     // so spawn it off to run on its own.
@@ -555,7 +2156,7 @@ pub async fn delete_group_item(ctx: &Context, msg: &Message, mut args: Args) ->
     event!(Level::INFO, "Connected to db...");
     msg.channel_id.say(&ctx.http, ":thumbsup: ...database connected for deleting a group item...").await?;
 
-    let mut _it = client
+    let _it = client
         .query("delete from public.group_items where id=$1", &[&db_item])
         .await?;
 
@@ -671,8 +2272,7 @@ pub async fn get_all_group_events(ctx: &Context, msg: &Message) -> CommandResult
     .password(get_db_pw())
     .dbname("farflungfellowship")
     .connect(NoTls)
-    .await
-    .unwrap();
+    .await?;
     
     // The connection object performs the actual communication with the database,
     // so spawn it off to run on its own.
@@ -684,7 +2284,7 @@ pub async fn get_all_group_events(ctx: &Context, msg: &Message) -> CommandResult
     event!(Level::INFO, "Connected to db...");
     msg.channel_id.say(&ctx.http, ":thumbsup: ...database connected for fetching timeline_events...").await?;
 
-    let mut it = client
+    let it = client
         .query_raw("SELECT id, day, month, event, year, logged_by FROM timeline_events", slice_iter(&[]))
         .await?;
 
@@ -738,7 +2338,7 @@ pub async fn add_group_event(ctx: &Context, msg: &Message, mut args: Args) -> Co
 
     let year = args.single::<String>().unwrap_or_default(); 
 
-    let to_be_added_msg = format!(":fork_and_knife: ...preparing to event: {} - {} - {} - {} - {}", event, logged_by, month, result_day.to_string(), year);
+    let to_be_added_msg = format!(":fork_and_knife: ...preparing to event: {} - {} - {} - {} - {}", event, logged_by, month, result_day, year);
 
     msg.channel_id.say(&ctx.http, to_be_added_msg).await?;
     
@@ -753,8 +2353,7 @@ pub async fn add_group_event(ctx: &Context, msg: &Message, mut args: Args) -> Co
     .password(get_db_pw())
     .dbname("farflungfellowship")
     .connect(NoTls)
-    .await
-    .unwrap();
+    .await?;
     
     // The connection object performs the actual communication with the database,
     // so spawn it off to run on its own.
@@ -780,7 +2379,7 @@ pub async fn add_group_event(ctx: &Context, msg: &Message, mut args: Args) -> Co
     // let db_month = &third_pass_month;
 
 
-    let mut _it = client
+    let _it = client
         .query("insert into public.timeline_events (event, logged_by, month, day, year) values($1, $2, $3, $4, $5)", &[&db_event, &db_logged_by, &db_month, &db_day, &db_year])
         .await?;
 
@@ -788,4 +2387,23 @@ pub async fn add_group_event(ctx: &Context, msg: &Message, mut args: Args) -> Co
 
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_range_reads_both_ends() {
+        assert_eq!(parse_range("3-8"), Some((3, 8)));
+        assert_eq!(parse_range(" 8 - 3 "), Some((8, 3)));
+    }
+
+    #[test]
+    fn parse_range_rejects_non_ranges() {
+        assert_eq!(parse_range("3"), None);
+        assert_eq!(parse_range("a-8"), None);
+        assert_eq!(parse_range("3-"), None);
+        assert_eq!(parse_range("-3-8"), None);
+    }
+}
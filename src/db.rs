@@ -0,0 +1,47 @@
+//! # Database
+//! Connection handling for the Postgres database Sunny shares with the campaign tables.
+
+use std::env;
+
+use tokio_postgres::{Client, Config, NoTls};
+use tracing::{event, instrument, Level};
+
+use crate::utils::{SunnyError, SunnyResult};
+
+/// Opens a new connection to the database and spawns its connection task.
+#[instrument]
+pub async fn connect() -> SunnyResult<Client> {
+    let password =
+        env::var("DB_PW").map_err(|_| SunnyError::log("Environment variable DB_PW not found"))?;
+
+    let (client, connection) = Config::new()
+        .host("localhost")
+        .user("sunny")
+        .port(5432)
+        .password(password)
+        .dbname("farflungfellowship")
+        .connect(NoTls)
+        .await
+        .map_err(|e| {
+            SunnyError::user_and_log(
+                "Couldn't reach the database :person_shrugging:",
+                format!("Failed to connect to database: {}", e).as_str(),
+            )
+        })?;
+
+    // The connection object performs the actual communication with the database,
+    // so spawn it off to run on its own.
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            event!(Level::ERROR, %e, "database connection error");
+        }
+    });
+
+    Ok(client)
+}
+
+/// Converts a database error into a [`SunnyError`] that is only logged.
+#[allow(clippy::needless_pass_by_value)]
+pub fn log_err(e: tokio_postgres::Error) -> SunnyError {
+    SunnyError::log(format!("Database query failed: {}", e).as_str())
+}
@@ -0,0 +1,173 @@
+use rand::{distributions::WeightedIndex, prelude::Distribution, rngs::SmallRng, SeedableRng};
+use serenity::{
+    client::Context,
+    model::id::{GuildId, UserId},
+};
+use tracing::instrument;
+
+use crate::{
+    db,
+    structs::{with_guild_state, AutoplaySource, TrackRecord},
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::queue::{self, EnqueueAt};
+
+/// Number of most recently played songs autoplay avoids repeating
+const RECENT: i64 = 5;
+
+/// Weight every candidate gets, so songs that were always skipped can still
+/// come up now and then
+const BASE_WEIGHT: f64 = 0.05;
+
+/// A song autoplay could pick
+#[derive(Debug)]
+struct Candidate {
+    url: String,
+    /// Fraction of the song that was listened to on average
+    score: f64,
+    /// Whether the song is one of the most recently played ones
+    recent: bool,
+}
+
+impl From<tokio_postgres::Row> for Candidate {
+    fn from(row: tokio_postgres::Row) -> Self {
+        Self {
+            url: row.get("url"),
+            score: row.get("score"),
+            recent: row.get("recent"),
+        }
+    }
+}
+
+/// Songs from the play history
+async fn history_candidates(guild_id: GuildId) -> SunnyResult<Vec<Candidate>> {
+    let rows = db::connect()
+        .await?
+        .query(
+            "SELECT url, avg(coalesce(least(listened_ms::float8 / nullif(duration_ms, 0), 1.0), 1.0)) AS score, url IN (SELECT url FROM play_history WHERE guild_id = $1 ORDER BY played_at DESC LIMIT $2) AS recent FROM play_history WHERE guild_id = $1 GROUP BY url ORDER BY max(played_at) DESC LIMIT 500",
+            &[&(guild_id.0 as i64), &RECENT],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    Ok(rows.into_iter().map(Candidate::from).collect())
+}
+
+/// Songs from a saved playlist, songs that weren't played yet count as fully
+/// listened to
+async fn playlist_candidates(
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    name: &str,
+) -> SunnyResult<Vec<Candidate>> {
+    let rows = db::connect()
+        .await?
+        .query(
+            "SELECT t.url, coalesce(h.score, 1.0) AS score, t.url IN (SELECT url FROM play_history WHERE guild_id = $1 ORDER BY played_at DESC LIMIT $4) AS recent FROM playlist_tracks t JOIN playlists p ON p.id = t.playlist_id LEFT JOIN (SELECT url, avg(coalesce(least(listened_ms::float8 / nullif(duration_ms, 0), 1.0), 1.0)) AS score FROM play_history WHERE guild_id = $1 GROUP BY url) h ON h.url = t.url WHERE p.guild_id = $1 AND p.owner_id IS NOT DISTINCT FROM $2 AND lower(p.name) = lower($3)",
+            &[
+                &(guild_id.0 as i64),
+                &owner.map(|o| o.0 as i64),
+                &name,
+                &RECENT,
+            ],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    Ok(rows.into_iter().map(Candidate::from).collect())
+}
+
+/// Files from the local library, files that weren't played yet count as fully
+/// listened to
+async fn library_candidates(guild_id: GuildId) -> SunnyResult<Vec<Candidate>> {
+    let rows = db::connect()
+        .await?
+        .query(
+            "SELECT l.path AS url, coalesce(h.score, 1.0) AS score, l.path IN (SELECT url FROM play_history WHERE guild_id = $1 ORDER BY played_at DESC LIMIT $2) AS recent FROM library_tracks l LEFT JOIN (SELECT url, avg(coalesce(least(listened_ms::float8 / nullif(duration_ms, 0), 1.0), 1.0)) AS score FROM play_history WHERE guild_id = $1 GROUP BY url) h ON h.url = l.path",
+            &[&(guild_id.0 as i64), &RECENT],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    Ok(rows.into_iter().map(Candidate::from).collect())
+}
+
+/// Picks a url, weighted toward songs people listened to instead of skipping
+fn pick(mut candidates: Vec<Candidate>) -> Option<String> {
+    if candidates.iter().any(|c| !c.recent) {
+        candidates.retain(|c| !c.recent);
+    }
+
+    let weights =
+        WeightedIndex::new(candidates.iter().map(|c| BASE_WEIGHT + c.score.max(0.0))).ok()?;
+    let mut rng = SmallRng::from_entropy();
+
+    Some(candidates.swap_remove(weights.sample(&mut rng)).url)
+}
+
+/// Enqueues a track from the guild's autoplay source if the queue is empty.
+#[instrument(skip(ctx))]
+pub async fn autoplay(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
+    let source =
+        if let Some(source) = with_guild_state(ctx, guild_id, |s| s.autoplay.clone()).await? {
+            source
+        } else {
+            return Ok(());
+        };
+
+    // Sunny left, nothing to keep going
+    let call_m = if let Some(call_m) = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+    {
+        call_m
+    } else {
+        return Ok(());
+    };
+
+    if !call_m.lock().await.queue().is_empty() {
+        return Ok(());
+    }
+
+    let candidates = match source {
+        AutoplaySource::History => history_candidates(guild_id).await?,
+        AutoplaySource::Playlist { owner, name } => {
+            playlist_candidates(guild_id, owner, &name).await?
+        }
+        AutoplaySource::Library => library_candidates(guild_id).await?,
+    };
+
+    let url = pick(candidates).ok_or_else(|| SunnyError::log("Nothing to autoplay"))?;
+    let bot_id = ctx.cache.current_user_id().await;
+
+    queue::play(
+        ctx,
+        guild_id,
+        url,
+        EnqueueAt::Back,
+        TrackRecord::autoplay(bot_id),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Sets the guild's autoplay source, starting right away if the queue is empty
+#[instrument(skip(ctx))]
+pub async fn set_autoplay(
+    ctx: &Context,
+    guild_id: GuildId,
+    source: Option<AutoplaySource>,
+) -> SunnyResult<()> {
+    let enabled = source.is_some();
+
+    with_guild_state(ctx, guild_id, |s| s.autoplay = source).await?;
+
+    if enabled {
+        autoplay(ctx, guild_id).await?;
+    }
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{num::NonZeroUsize, time::Duration};
 
 use serenity::{
     builder::CreateActionRow,
@@ -6,21 +6,86 @@ use serenity::{
     futures::prelude::*,
     model::{
         channel::Message,
-        id::{ChannelId, GuildId},
+        id::{ChannelId, GuildId, UserId},
         interactions::{message_component::ButtonStyle, InteractionResponseType},
     },
 };
 use songbird::tracks::TrackHandle;
 use tracing::instrument;
+use uuid::Uuid;
 
-use crate::utils::{SunnyError, SunnyResult};
+use crate::{
+    structs::TrackRecord,
+    utils::{SunnyError, SunnyResult},
+};
 
 use super::*;
 
+const FIRST_ID: &str = "q_first";
 const PREV_ID: &str = "q_prev";
 const NEXT_ID: &str = "q_next";
+const LAST_ID: &str = "q_last";
+const PAGE_ID: &str = "q_page";
+const TRACK_ID: &str = "q_track";
+const REMOVE_ID: &str = "q_remove";
+const PLAY_NEXT_ID: &str = "q_play_next";
+const UP_ID: &str = "q_up";
+const DOWN_ID: &str = "q_down";
+
+/// Songs per page
+const PAGE_LEN: usize = 10;
+
+/// Most options a select menu can have
+const MAX_OPTIONS: usize = 25;
+
+/// Longest label a select menu option can have
+const MAX_LABEL: usize = 100;
+
+/// How often a live queue message checks if the queue changed
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Index of the last page, the current song isn't on any
+const fn last_page(queue_len: usize) -> usize {
+    queue_len.saturating_sub(2) / PAGE_LEN
+}
 
-fn generate_embed(queue: &[TrackHandle], page: usize) -> serenity::builder::CreateEmbed {
+/// What a queue message shows
+#[derive(Clone, Copy, Debug, Default)]
+struct View {
+    page: usize,
+    /// Song picked in the select menu, to remove or move
+    selected: Option<Uuid>,
+}
+
+impl View {
+    /// Keeps the view valid after the queue changed
+    fn fit(&mut self, queue: &[TrackHandle]) {
+        self.page = self.page.min(last_page(queue.len()));
+
+        if let Some(selected) = self.selected {
+            if !queue.iter().skip(1).any(|t| t.uuid() == selected) {
+                self.selected = None;
+            }
+        }
+    }
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+
+    let mut truncated: String = s.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+fn generate_embed(
+    queue: &[TrackHandle],
+    records: &[Option<TrackRecord>],
+    waits: &[Option<Duration>],
+    page: usize,
+) -> serenity::builder::CreateEmbed {
     let mut titles = Vec::with_capacity(10);
     let mut artists = Vec::with_capacity(10);
     let mut durs = Vec::with_capacity(10);
@@ -28,10 +93,21 @@ fn generate_embed(queue: &[TrackHandle], page: usize) -> serenity::builder::Crea
         a + b.metadata().duration.unwrap_or_default()
     });
 
-    for (i, track) in queue.iter().enumerate().skip(1 + page * 10).take(10) {
+    for (i, track) in queue
+        .iter()
+        .enumerate()
+        .skip(1 + page * PAGE_LEN)
+        .take(PAGE_LEN)
+    {
         let m = track.metadata();
 
-        let title = format!("**{}.** {}\n", i, get_title(m));
+        let requester = records
+            .get(i)
+            .and_then(Option::as_ref)
+            .map(|r| format!(" - <@{}>", r.requester))
+            .unwrap_or_default();
+
+        let title = format!("**{}.** {}{}\n", i, get_title(m), requester);
         titles.push(title);
 
         let artist = format!("{}\n", get_artist(m));
@@ -41,7 +117,14 @@ fn generate_embed(queue: &[TrackHandle], page: usize) -> serenity::builder::Crea
         let seconds = duration.as_secs() % 60;
         let minutes = duration.as_secs() / 60;
 
-        durs.push(format!("[{}:{:02}]\n", minutes, seconds));
+        let wait = waits
+            .get(i)
+            .copied()
+            .flatten()
+            .map(|w| format!(" plays in {}", format_wait(w)))
+            .unwrap_or_default();
+
+        durs.push(format!("[{}:{:02}]{}\n", minutes, seconds, wait));
     }
 
     let mut e = serenity::builder::CreateEmbed::default();
@@ -50,10 +133,17 @@ fn generate_embed(queue: &[TrackHandle], page: usize) -> serenity::builder::Crea
     if let Some(track) = queue.first() {
         let m = track.metadata();
 
+        let requested = records
+            .first()
+            .and_then(Option::as_ref)
+            .map(|r| format!("\n**Requested by:** {}", requested_by(r)))
+            .unwrap_or_default();
+
         e.description(format!(
-            "**Now Playing:** {} by {}",
+            "**Now Playing:** {} by {}{}",
             get_title(m),
-            get_artist(m)
+            get_artist(m),
+            requested
         ));
     }
 
@@ -83,7 +173,7 @@ fn generate_embed(queue: &[TrackHandle], page: usize) -> serenity::builder::Crea
         f.text(format!(
             "Page {}/{} | Total Duration: {:02}:{:02}",
             page + 1,
-            (queue.len() / 10 + 1),
+            last_page(queue.len()) + 1,
             minutes,
             seconds,
         ))
@@ -92,49 +182,177 @@ fn generate_embed(queue: &[TrackHandle], page: usize) -> serenity::builder::Crea
     e
 }
 
-fn build_action_row(page: usize, queue_len: usize) -> CreateActionRow {
-    let pages = queue_len / 10;
-    let mut row = CreateActionRow::default();
-
-    // Previous button
-    if page > 0 {
-        row.create_button(|b| {
-            b.style(ButtonStyle::Primary);
-            b.label("Previous");
-            b.custom_id(PREV_ID);
-            b.disabled(false)
+fn nav_button(row: &mut CreateActionRow, id: &str, label: &str, enabled: bool) {
+    row.create_button(|b| {
+        b.style(if enabled {
+            ButtonStyle::Primary
+        } else {
+            ButtonStyle::Danger
         });
-    } else {
-        row.create_button(|b| {
-            b.style(ButtonStyle::Danger);
-            b.label("Previous");
-            b.custom_id(PREV_ID);
-            b.disabled(true)
+        b.label(label);
+        b.custom_id(id);
+        b.disabled(!enabled)
+    });
+}
+
+fn action_button(
+    row: &mut CreateActionRow,
+    id: &str,
+    label: &str,
+    style: ButtonStyle,
+    enabled: bool,
+) {
+    row.create_button(|b| {
+        b.style(style);
+        b.label(label);
+        b.custom_id(id);
+        b.disabled(!enabled)
+    });
+}
+
+fn build_action_rows(queue: &[TrackHandle], view: View) -> Vec<CreateActionRow> {
+    let last = last_page(queue.len());
+    let mut rows = Vec::new();
+
+    let mut nav = CreateActionRow::default();
+    nav_button(&mut nav, FIRST_ID, "First", view.page > 0);
+    nav_button(&mut nav, PREV_ID, "Previous", view.page > 0);
+    nav_button(&mut nav, NEXT_ID, "Next", view.page < last);
+    nav_button(&mut nav, LAST_ID, "Last", view.page < last);
+    rows.push(nav);
+
+    if last > 0 {
+        // Pages around the current one when there are too many to list
+        let first = view
+            .page
+            .saturating_sub(MAX_OPTIONS / 2)
+            .min((last + 1).saturating_sub(MAX_OPTIONS));
+
+        let mut pages = CreateActionRow::default();
+        pages.create_select_menu(|m| {
+            m.custom_id(PAGE_ID);
+            m.placeholder("Jump to page");
+            m.options(|o| {
+                for page in (first..=last).take(MAX_OPTIONS) {
+                    o.create_option(|o| {
+                        o.label(format!("Page {}", page + 1));
+                        o.value(page);
+                        o.default_selection(page == view.page)
+                    });
+                }
+                o
+            })
         });
+        rows.push(pages);
     }
 
-    // Next button
-    if pages >= 1 && page < pages {
-        row.create_button(|b| {
-            b.style(ButtonStyle::Primary);
-            b.label("Next");
-            b.custom_id(NEXT_ID);
-            b.disabled(false)
-        });
-    } else {
-        row.create_button(|b| {
-            b.style(ButtonStyle::Danger);
-            b.label("Next");
-            b.custom_id(NEXT_ID);
-            b.disabled(true)
+    let on_page: Vec<(usize, &TrackHandle)> = queue
+        .iter()
+        .enumerate()
+        .skip(1 + view.page * PAGE_LEN)
+        .take(PAGE_LEN)
+        .collect();
+
+    if !on_page.is_empty() {
+        let mut tracks = CreateActionRow::default();
+        tracks.create_select_menu(|m| {
+            m.custom_id(TRACK_ID);
+            m.placeholder("Pick a song to remove or move");
+            m.options(|o| {
+                for (i, track) in &on_page {
+                    let m = track.metadata();
+                    o.create_option(|o| {
+                        o.label(truncate(&format!("{}. {}", i, get_title(m)), MAX_LABEL));
+                        o.description(truncate(get_artist(m), MAX_LABEL));
+                        o.value(track.uuid());
+                        o.default_selection(view.selected == Some(track.uuid()))
+                    });
+                }
+                o
+            })
         });
+        rows.push(tracks);
+
+        let picked = view.selected.is_some();
+        let mut actions = CreateActionRow::default();
+        action_button(
+            &mut actions,
+            REMOVE_ID,
+            "Remove",
+            ButtonStyle::Danger,
+            picked,
+        );
+        action_button(
+            &mut actions,
+            PLAY_NEXT_ID,
+            "Play next",
+            ButtonStyle::Secondary,
+            picked,
+        );
+        action_button(
+            &mut actions,
+            UP_ID,
+            "Move up",
+            ButtonStyle::Secondary,
+            picked,
+        );
+        action_button(
+            &mut actions,
+            DOWN_ID,
+            "Move down",
+            ButtonStyle::Secondary,
+            picked,
+        );
+        rows.push(actions);
     }
 
-    row
+    rows
 }
 
+/// Removes or moves the picked song, with the same checks as `remove` and
+/// `move`
+async fn act_on_track(
+    ctx: &Context,
+    guild_id: GuildId,
+    action: &str,
+    selected: Option<Uuid>,
+    user_id: UserId,
+) -> SunnyResult<()> {
+    let selected = selected.ok_or_else(|| SunnyError::user("Pick a song first"))?;
+
+    let cq = get_queue(ctx, guild_id).await?;
+    let index = cq
+        .iter()
+        .position(|t| t.uuid() == selected)
+        .and_then(NonZeroUsize::new)
+        .ok_or_else(|| SunnyError::user("That song isn't in the queue anymore"))?;
+
+    if action == REMOVE_ID {
+        queue::remove_at(ctx, guild_id, index, user_id).await?;
+        return Ok(());
+    }
+
+    let from = index.get();
+    let to = match action {
+        PLAY_NEXT_ID => 1,
+        UP_ID => from.saturating_sub(1).max(1),
+        _ => (from + 1).min(cq.len() - 1),
+    };
+
+    if from != to {
+        queue::move_to(ctx, guild_id, from, to).await?;
+    }
+
+    Ok(())
+}
+
+fn uuids(queue: &[TrackHandle]) -> Vec<Uuid> {
+    queue.iter().map(TrackHandle::uuid).collect()
+}
+
+/// Gets the tracks in the queue, the current one first
 #[instrument(skip(ctx))]
-async fn get_queue(ctx: &Context, guild_id: GuildId) -> SunnyResult<Vec<TrackHandle>> {
+pub async fn get_queue(ctx: &Context, guild_id: GuildId) -> SunnyResult<Vec<TrackHandle>> {
     Ok(songbird::get(ctx)
         .await
         .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
@@ -155,12 +373,14 @@ pub async fn send_embed(
 ) -> SunnyResult<()> {
     // Retrieve the current queue
     let cq = get_queue(ctx, guild_id).await?;
+    let records = get_records(ctx, guild_id, &cq).await?;
+    let waits = queue_waits(ctx, guild_id, &cq).await?;
 
     // Send initial queue message
     let message = channel_id
         .send_message(&ctx.http, |m| {
-            m.components(|c| c.set_action_rows(vec![build_action_row(0, cq.len())]));
-            m.set_embed(generate_embed(&cq, 0))
+            m.components(|c| c.set_action_rows(build_action_rows(&cq, View::default())));
+            m.set_embed(generate_embed(&cq, &records, &waits, 0))
         })
         .await
         .map_err(|e| SunnyError::log(format!("Unable to send queue message: {:?}", e).as_str()))?;
@@ -169,8 +389,8 @@ pub async fn send_embed(
 }
 
 async fn await_interactions(ctx: &Context, mut msg: Message, guild_id: GuildId) -> SunnyResult<()> {
-    // Currently shown page
-    let mut page: usize = 0;
+    // Currently shown page and picked song
+    let mut view = View::default();
 
     // await interactions i.e. button presses
     let mut collector = msg
@@ -178,28 +398,83 @@ async fn await_interactions(ctx: &Context, mut msg: Message, guild_id: GuildId)
         .timeout(Duration::from_secs(3600)) // 1h
         .await;
 
-    // Process button presses
-    while let Some(mci) = collector.next().await {
-        if mci.data.custom_id == NEXT_ID {
-            page += 1;
-        } else if mci.data.custom_id == PREV_ID {
-            page = if let Some(p) = page.checked_sub(1) {
-                p
-            } else {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    // The first tick is right away
+    interval.tick().await;
+
+    let mut shown = uuids(&get_queue(ctx, guild_id).await?);
+
+    loop {
+        let mci = tokio::select! {
+            mci = collector.next() => match mci {
+                Some(mci) => mci,
+                None => break,
+            },
+            _ = interval.tick() => {
+                // Gone once Sunny leaves
+                let cq = match get_queue(ctx, guild_id).await {
+                    Ok(cq) => cq,
+                    Err(_) => break,
+                };
+
+                if uuids(&cq) == shown {
+                    continue;
+                }
+
+                shown = uuids(&cq);
+                view.fit(&cq);
+                let records = get_records(ctx, guild_id, &cq).await?;
+                let waits = queue_waits(ctx, guild_id, &cq).await?;
+
+                msg.edit(&ctx.http, |e| {
+                    e.components(|c| c.set_action_rows(build_action_rows(&cq, view)));
+                    e.set_embed(generate_embed(&cq, &records, &waits, view.page))
+                })
+                .await
+                .map_err(|e| SunnyError::log(format!("Unable to refresh queue {:?}", e).as_str()))?;
+
                 continue;
-            };
-        } else {
-            continue;
+            }
+        };
+
+        let value = mci.data.values.first();
+
+        match mci.data.custom_id.as_str() {
+            FIRST_ID => view.page = 0,
+            PREV_ID => view.page = view.page.saturating_sub(1),
+            NEXT_ID => view.page += 1,
+            // Fit to the last page below
+            LAST_ID => view.page = usize::MAX,
+            PAGE_ID => match value.and_then(|v| v.parse().ok()) {
+                Some(page) => view.page = page,
+                None => continue,
+            },
+            TRACK_ID => view.selected = value.and_then(|v| Uuid::parse_str(v).ok()),
+            action @ (REMOVE_ID | PLAY_NEXT_ID | UP_ID | DOWN_ID) => {
+                if let Err(e) =
+                    act_on_track(ctx, guild_id, action, view.selected, mci.user.id).await
+                {
+                    let content = e.user_message().unwrap_or("Couldn't do that");
+                    reply_ephemeral(ctx, &mci, content).await?;
+                    continue;
+                }
+            }
+            _ => continue,
         }
 
         let cq = get_queue(ctx, guild_id).await?;
+        let records = get_records(ctx, guild_id, &cq).await?;
+        let waits = queue_waits(ctx, guild_id, &cq).await?;
 
-        // Change the embed + buttons after page change
+        shown = uuids(&cq);
+        view.fit(&cq);
+
+        // Change the embed + components after a page change or an action
         mci.create_interaction_response(&ctx.http, |cir| {
             cir.kind(InteractionResponseType::UpdateMessage)
                 .interaction_response_data(|m| {
-                    m.add_embed(generate_embed(&cq, page));
-                    m.components(|c| c.set_action_rows(vec![build_action_row(page, cq.len())]))
+                    m.add_embed(generate_embed(&cq, &records, &waits, view.page));
+                    m.components(|c| c.set_action_rows(build_action_rows(&cq, view)))
                 })
         })
         .await
@@ -208,16 +483,15 @@ async fn await_interactions(ctx: &Context, mut msg: Message, guild_id: GuildId)
         })?;
     }
 
-    let guild_id = msg
-        .guild_id
-        .ok_or_else(|| SunnyError::log("message guild id could not be found"))?;
-
-    let cq = get_queue(ctx, guild_id).await?;
+    // Sunny may have left the call in the meantime
+    let cq = get_queue(ctx, guild_id).await.unwrap_or_default();
+    let records = get_records(ctx, guild_id, &cq).await?;
+    let waits = queue_waits(ctx, guild_id, &cq).await?;
 
     // Remove buttons after timeout
     msg.edit(&ctx.http, |e| {
         e.components(|c| c);
-        e.set_embed(generate_embed(&cq, page))
+        e.set_embed(generate_embed(&cq, &records, &waits, view.page))
     })
     .await
     .map_err(|e| SunnyError::log(format!("Unable clear buttons {:?}", e).as_str()))?;
@@ -0,0 +1,244 @@
+use std::{
+    sync::{MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use serenity::{client::Context, model::id::GuildId};
+use songbird::tracks::{LoopState, PlayMode, TrackHandle, TrackQueue, TrackState};
+use tracing::instrument;
+
+use crate::{
+    structs::{with_guild_state, FadingOut, FadingTracks},
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::source::end_position;
+
+/// Longest crossfade `crossfade` accepts
+pub const MAX_CROSSFADE: Duration = Duration::from_secs(12);
+
+/// How often playing tracks check if it's time to crossfade
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Fading out on commands is capped so they don't take forever to reply
+const MAX_FADE_OUT: Duration = Duration::from_secs(3);
+
+/// Time between volume changes of a fade
+const STEP: Duration = Duration::from_millis(50);
+
+/// Ramps the volume of `track` from `from` to `to` over `over`, stopping
+/// early if the track is gone. The ramp holds while the track is paused.
+pub async fn ramp(track: TrackHandle, from: f32, to: f32, over: Duration) {
+    let steps = (over.as_millis() / STEP.as_millis()).max(1) as u32;
+
+    let mut step = 0;
+    while step < steps {
+        tokio::time::sleep(STEP).await;
+
+        match track.get_info().await {
+            Ok(info) if info.playing == PlayMode::Pause => continue,
+            Ok(_) => step += 1,
+            Err(_) => return,
+        }
+
+        let volume = from + (to - from) * step as f32 / steps as f32;
+        if track.set_volume(volume).is_err() {
+            return;
+        }
+    }
+}
+
+fn lock(fading: &FadingTracks) -> MutexGuard<'_, Vec<TrackHandle>> {
+    fading.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Moves the queue on from its playing track `current` to the next one right
+/// away, fading one into the other over `over`. `current` leaves the queue so
+/// the next track can't be moved while both play, it's kept with the guild's
+/// songs fading out until it's silent and stopped.
+///
+/// Returns whether it did, `current` has to be the front of the queue.
+pub async fn hand_over(
+    ctx: &Context,
+    guild_id: GuildId,
+    queue: &TrackQueue,
+    current: &TrackHandle,
+    over: Duration,
+) -> bool {
+    let next = match queue.current_queue().as_slice() {
+        [front, next, ..] if front.uuid() == current.uuid() => next.clone(),
+        _ => return false,
+    };
+
+    let from = match current.get_info().await {
+        Ok(info) => info.volume,
+        Err(_) => return false,
+    };
+    let (fading, to) =
+        match with_guild_state(ctx, guild_id, |s| (s.fading_out.clone(), s.music_volume())).await {
+            Ok(state) => state,
+            Err(_) => return false,
+        };
+
+    let handed = queue.modify_queue(|q| match q.front() {
+        Some(front) if front.uuid() == current.uuid() => q.pop_front(),
+        _ => None,
+    });
+    let handed = match handed {
+        Some(handed) => handed,
+        None => return false,
+    };
+
+    if next.set_volume(0.0).and_then(|_| next.play()).is_err() {
+        queue.modify_queue(|q| q.push_front(handed));
+        return false;
+    }
+
+    lock(&fading).push(current.clone());
+
+    let current = current.clone();
+    tokio::spawn(async move {
+        ramp(current.clone(), from, 0.0, over).await;
+        let _ = current.stop();
+
+        lock(&fading).retain(|t| t.uuid() != current.uuid());
+    });
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        ramp(next.clone(), 0.0, to, over).await;
+
+        // The music may have been ducked or brought back up in the meantime
+        if let Ok(volume) = with_guild_state(&ctx, guild_id, |s| s.music_volume()).await {
+            let _ = next.set_volume(volume);
+        }
+    });
+
+    true
+}
+
+/// Starts the next track in the queue when `track` has `crossfade` or less
+/// left, fading one into the other.
+///
+/// Returns whether it did, `track` only crossfades once.
+pub async fn crossfade(
+    ctx: &Context,
+    guild_id: GuildId,
+    queue: &TrackQueue,
+    state: &TrackState,
+    track: &TrackHandle,
+    crossfade: Duration,
+) -> bool {
+    // Looping tracks start over instead of ending
+    if state.loops != LoopState::Finite(0) {
+        return false;
+    }
+
+    let remaining = match end_position(track).await {
+        Some(end) => end.saturating_sub(state.position),
+        None => return false,
+    };
+
+    if remaining > crossfade {
+        return false;
+    }
+
+    hand_over(ctx, guild_id, queue, track, remaining).await
+}
+
+/// Songs of the guild that are crossfading out
+pub async fn fading_out(ctx: &Context, guild_id: GuildId) -> SunnyResult<Vec<TrackHandle>> {
+    let fading = with_guild_state(ctx, guild_id, |s| s.fading_out.clone()).await?;
+    let tracks = lock(&fading).clone();
+
+    Ok(tracks)
+}
+
+/// Stops the songs that are crossfading out
+#[instrument(skip(ctx))]
+pub async fn stop_fading_out(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
+    let fading = with_guild_state(ctx, guild_id, |s| s.fading_out.clone()).await?;
+
+    for track in lock(&fading).drain(..) {
+        let _ = track.stop();
+    }
+
+    Ok(())
+}
+
+/// Fades out the given tracks over the guild's crossfade duration, capped to
+/// a few seconds
+async fn fade_out(ctx: &Context, guild_id: GuildId, tracks: Vec<TrackHandle>) -> SunnyResult<()> {
+    let audio = with_guild_state(ctx, guild_id, |s| s.audio.clone()).await?;
+    let fade = match audio.read().ok().and_then(|a| a.crossfade) {
+        Some(fade) => fade.min(MAX_FADE_OUT),
+        None => return Ok(()),
+    };
+
+    let mut ramps = Vec::new();
+    for track in tracks {
+        let info = match track.get_info().await {
+            Ok(info) if info.playing == PlayMode::Play => info,
+            _ => continue,
+        };
+
+        track.typemap().write().await.insert::<FadingOut>(());
+        ramps.push(tokio::spawn(ramp(track, info.volume, 0.0, fade)));
+    }
+
+    for ramp in ramps {
+        let _ = ramp.await;
+    }
+
+    Ok(())
+}
+
+/// Gets the guild's queue
+async fn get_queue(ctx: &Context, guild_id: GuildId) -> SunnyResult<Vec<TrackHandle>> {
+    Ok(songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?
+        .lock()
+        .await
+        .queue()
+        .current_queue())
+}
+
+/// Fades out the current track, before skipping it
+#[instrument(skip(ctx))]
+pub async fn fade_out_current(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
+    let current = get_queue(ctx, guild_id)
+        .await?
+        .into_iter()
+        .take(1)
+        .collect();
+
+    fade_out(ctx, guild_id, current).await
+}
+
+/// Fades out everything in the queue that's playing, before stopping or
+/// leaving. A song crossfading out is on its way out already.
+#[instrument(skip(ctx))]
+pub async fn fade_out_all(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
+    let queue = get_queue(ctx, guild_id).await?;
+
+    fade_out(ctx, guild_id, queue).await
+}
+
+/// Sets how long tracks crossfade, or turns it off with `None`
+#[instrument(skip(ctx))]
+pub async fn set_crossfade(
+    ctx: &Context,
+    guild_id: GuildId,
+    crossfade: Option<Duration>,
+) -> SunnyResult<()> {
+    let audio = with_guild_state(ctx, guild_id, |s| s.audio.clone()).await?;
+    audio
+        .write()
+        .map_err(|_| SunnyError::log("Audio settings lock poisoned"))?
+        .crossfade = crossfade;
+
+    Ok(())
+}
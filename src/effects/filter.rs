@@ -0,0 +1,28 @@
+use serenity::{client::Context, model::id::GuildId};
+use tracing::instrument;
+
+use crate::{
+    structs::{with_guild_state, FilterPreset},
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::source::restart_current;
+
+/// Sets the guild's filter, or turns it off with `None`.
+///
+/// Queued tracks pick it up when they start, the current track is recreated
+/// at its position to apply it right away.
+#[instrument(skip(ctx))]
+pub async fn set_filter(
+    ctx: &Context,
+    guild_id: GuildId,
+    filter: Option<FilterPreset>,
+) -> SunnyResult<()> {
+    let audio = with_guild_state(ctx, guild_id, |s| s.audio.clone()).await?;
+    audio
+        .write()
+        .map_err(|_| SunnyError::log("Audio settings lock poisoned"))?
+        .filter = filter;
+
+    restart_current(ctx, guild_id).await
+}
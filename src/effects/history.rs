@@ -0,0 +1,288 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serenity::{
+    client::Context,
+    model::id::{GuildId, UserId},
+};
+use songbird::tracks::TrackHandle;
+use tracing::instrument;
+
+use crate::{
+    db,
+    structs::{with_guild_state, TrackRecord},
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{
+    get_song,
+    queue::{self, EnqueueAt},
+    split_duration,
+};
+
+/// Number of history entries shown per page
+pub const PAGE_SIZE: i64 = 10;
+
+/// A played track in a guild's history
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub url: String,
+    pub title: String,
+    pub requester: Option<UserId>,
+    pub played_at: DateTime<Utc>,
+    pub listened: Duration,
+    pub failed: bool,
+}
+
+impl From<tokio_postgres::Row> for HistoryEntry {
+    fn from(row: tokio_postgres::Row) -> Self {
+        Self {
+            url: row.get("url"),
+            title: row.get("title"),
+            requester: row
+                .get::<_, Option<i64>>("requester_id")
+                .map(|id| UserId(id as u64)),
+            played_at: row.get("played_at"),
+            listened: Duration::from_millis(row.get::<_, i64>("listened_ms") as u64),
+            failed: row.get("failed"),
+        }
+    }
+}
+
+/// Adds a track that started playing to the history, tracks that are
+/// resumed after a pause are only added once.
+#[instrument(skip(ctx))]
+pub async fn record_play(ctx: &Context, guild_id: GuildId, track: &TrackHandle) -> SunnyResult<()> {
+    let uuid = track.uuid();
+
+    let (logged, requester) = with_guild_state(ctx, guild_id, |s| {
+        (
+            s.history_ids.contains_key(&uuid),
+            s.records.get(&uuid).map(|r| r.requester),
+        )
+    })
+    .await?;
+
+    if logged {
+        return Ok(());
+    }
+
+    let m = track.metadata();
+    let url = m
+        .source_url
+        .clone()
+        .ok_or_else(|| SunnyError::log("Track has no source url"))?;
+
+    let client = db::connect().await?;
+
+    let row = client
+        .query_one(
+            "INSERT INTO play_history (guild_id, url, title, requester_id, duration_ms) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+            &[
+                &(guild_id.0 as i64),
+                &url,
+                &get_song(m),
+                &requester.map(|u| u.0 as i64),
+                &m.duration.map(|d| d.as_millis() as i64),
+            ],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    let id: i32 = row.get("id");
+
+    with_guild_state(ctx, guild_id, |s| s.history_ids.insert(uuid, id)).await?;
+
+    Ok(())
+}
+
+/// Stores how long a finished track was listened to and whether its stream
+/// failed, a retried track adds to the time of the original.
+#[instrument(skip(ctx))]
+pub async fn record_end(
+    ctx: &Context,
+    guild_id: GuildId,
+    track: &TrackHandle,
+    listened: Duration,
+    failed: bool,
+) -> SunnyResult<()> {
+    let uuid = track.uuid();
+
+    let id =
+        if let Some(id) = with_guild_state(ctx, guild_id, |s| s.history_ids.remove(&uuid)).await? {
+            id
+        } else {
+            return Ok(());
+        };
+
+    db::connect()
+        .await?
+        .execute(
+            "UPDATE play_history SET listened_ms = listened_ms + $1, failed = $2 WHERE id = $3",
+            &[&(listened.as_millis() as i64), &failed, &id],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    Ok(())
+}
+
+/// Gets a page (starting at 0) of the history, most recent first, and the
+/// total number of entries
+#[instrument]
+pub async fn page(guild_id: GuildId, page: i64) -> SunnyResult<(Vec<HistoryEntry>, i64)> {
+    let client = db::connect().await?;
+    let db_guild_id = guild_id.0 as i64;
+
+    let total: i64 = client
+        .query_one(
+            "SELECT count(*) AS total FROM play_history WHERE guild_id = $1",
+            &[&db_guild_id],
+        )
+        .await
+        .map_err(db::log_err)?
+        .get("total");
+
+    let entries = client
+        .query(
+            "SELECT url, title, requester_id, played_at, listened_ms, failed FROM play_history WHERE guild_id = $1 ORDER BY played_at DESC, id DESC LIMIT $2 OFFSET $3",
+            &[&db_guild_id, &PAGE_SIZE, &(page * PAGE_SIZE)],
+        )
+        .await
+        .map_err(db::log_err)?
+        .into_iter()
+        .map(HistoryEntry::from)
+        .collect();
+
+    Ok((entries, total))
+}
+
+/// Gets the `n`th (starting at 1) most recently played entry
+#[instrument]
+pub async fn entry(guild_id: GuildId, n: usize) -> SunnyResult<HistoryEntry> {
+    if n == 0 {
+        return Err(SunnyError::user(
+            "A history index of 0 is invalid (The history starts at 1)",
+        ));
+    }
+
+    db::connect()
+        .await?
+        .query_opt(
+            "SELECT url, title, requester_id, played_at, listened_ms, failed FROM play_history WHERE guild_id = $1 ORDER BY played_at DESC, id DESC OFFSET $2 LIMIT 1",
+            &[&(guild_id.0 as i64), &(n as i64 - 1)],
+        )
+        .await
+        .map_err(db::log_err)?
+        .map(HistoryEntry::from)
+        .ok_or_else(|| SunnyError::user(format!("There's no song at position {} in the history", n).as_str()))
+}
+
+/// Gets the entry played before the current track, returning whether a track
+/// is currently playing
+#[instrument(skip(ctx))]
+pub async fn previous(ctx: &Context, guild_id: GuildId) -> SunnyResult<(HistoryEntry, bool)> {
+    let call_m = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?;
+
+    let current = call_m.lock().await.queue().current().map(|t| t.uuid());
+
+    // The current track is already the most recent entry once it started
+    let logged = match current {
+        Some(uuid) => {
+            with_guild_state(ctx, guild_id, |s| s.history_ids.contains_key(&uuid)).await?
+        }
+        None => false,
+    };
+
+    let n = if logged { 2 } else { 1 };
+
+    let prev = entry(guild_id, n)
+        .await
+        .map_err(|_| SunnyError::user("There's no previous song"))?;
+
+    Ok((prev, current.is_some()))
+}
+
+/// Plays the entry before the current track again, skipping the current one.
+///
+/// Returns the entry.
+#[instrument(skip(ctx))]
+pub async fn back(
+    ctx: &Context,
+    guild_id: GuildId,
+    requester: UserId,
+) -> SunnyResult<HistoryEntry> {
+    let (prev, playing) = previous(ctx, guild_id).await?;
+
+    queue::play(
+        ctx,
+        guild_id,
+        prev.url.clone(),
+        EnqueueAt::Front,
+        TrackRecord::new(requester, "back"),
+    )
+    .await?;
+
+    if playing {
+        queue::skip(ctx, guild_id).await?;
+    }
+
+    Ok(prev)
+}
+
+/// Generates an embed showing a page (starting at 0) of the history
+pub fn generate_embed(
+    entries: &[HistoryEntry],
+    page: i64,
+    total: i64,
+) -> serenity::builder::CreateEmbed {
+    let mut e = serenity::builder::CreateEmbed::default();
+    e.author(|a| a.name("Recently Played"));
+
+    let lines: Vec<String> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let (min, sec) = split_duration(entry.listened);
+            let requester = entry
+                .requester
+                .map(|r| format!(" - <@{}>", r))
+                .unwrap_or_default();
+
+            let failed = if entry.failed { " :warning:" } else { "" };
+
+            format!(
+                "**{}.** [{}]({}){} <t:{}:R> [{}:{:02}]{}",
+                page * PAGE_SIZE + i as i64 + 1,
+                entry.title,
+                entry.url,
+                requester,
+                entry.played_at.timestamp(),
+                min,
+                sec,
+                failed
+            )
+        })
+        .collect();
+
+    if lines.is_empty() {
+        e.description("Nothing played yet");
+    } else {
+        e.description(lines.join("\n"));
+    }
+
+    e.footer(|f| {
+        f.text(format!(
+            "Page {}/{} | {} songs played",
+            page + 1,
+            (total - 1).max(0) / PAGE_SIZE + 1,
+            total
+        ))
+    });
+
+    e
+}
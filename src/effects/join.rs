@@ -9,8 +9,8 @@ use songbird::{Call, Event, TrackEvent};
 use tracing::instrument;
 
 use crate::{
-    handlers::{TimeoutHandler, TrackPlayNotifier},
-    structs::EventConfig,
+    handlers::{TimeoutHandler, TrackEndNotifier, TrackPlayNotifier},
+    structs::{with_guild_state, ChannelBinding, EventConfig},
     utils::{SunnyError, SunnyResult},
 };
 
@@ -26,6 +26,11 @@ async fn add_events(cfg: &EventConfig, call_m: Arc<Mutex<Call>>) {
         TrackPlayNotifier { cfg: cfg.clone() },
     );
 
+    call.add_global_event(
+        Event::Track(TrackEvent::End),
+        TrackEndNotifier { cfg: cfg.clone() },
+    );
+
     call.add_global_event(
         Event::Periodic(Duration::from_secs(60), None),
         TimeoutHandler {
@@ -52,5 +57,13 @@ pub async fn join(cfg: &EventConfig) -> SunnyResult<Arc<Mutex<Call>>> {
 
     add_events(cfg, call_m.clone()).await;
 
+    with_guild_state(&cfg.ctx, cfg.guild_id, |s| {
+        s.binding = Some(ChannelBinding {
+            text_channel_id: cfg.text_channel_id,
+            voice_channel_id: cfg.voice_channel_id,
+        });
+    })
+    .await?;
+
     Ok(call_m)
 }
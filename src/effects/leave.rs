@@ -1,7 +1,12 @@
 use serenity::{client::Context, model::id::GuildId};
-use tracing::instrument;
+use tracing::{event, instrument, Level};
 
-use crate::utils::{SunnyError, SunnyResult};
+use crate::{
+    effects::fade,
+    emit,
+    structs::with_guild_state,
+    utils::{SunnyError, SunnyResult},
+};
 
 #[instrument(skip(ctx))]
 pub async fn leave(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
@@ -9,10 +14,42 @@ pub async fn leave(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
         .await
         .ok_or_else(|| SunnyError::log("Couldn't get Songbird"))?;
 
+    // Leaving without a call fails below anyway
+    let res = fade::fade_out_all(ctx, guild_id).await;
+    emit!(res, Level::DEBUG);
+
     songbird
         .remove(guild_id)
         .await
         .map_err(|e| SunnyError::user_and_log("Failed to leave", e.to_string().as_str()))?;
 
+    // Leaving stopped the songs crossfading out too
+    fade::stop_fading_out(ctx, guild_id).await?;
+
+    // The tracks went with the call without ending, so nothing else forgets them
+    let now_playing = with_guild_state(ctx, guild_id, |s| {
+        s.binding = None;
+        s.records.clear();
+        s.history_ids.clear();
+        s.retried.clear();
+        s.unshuffled = None;
+
+        for layer in s.layers.values_mut() {
+            layer.tracks.clear();
+        }
+
+        s.now_playing.take()
+    })
+    .await?;
+
+    // Its task notices and stops on its own
+    if let Some(now_playing) = now_playing {
+        now_playing
+            .channel_id
+            .delete_message(&ctx.http, now_playing.message_id)
+            .await
+            .ok();
+    }
+
     Ok(())
 }
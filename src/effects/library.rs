@@ -0,0 +1,288 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use serenity::builder::CreateEmbed;
+use tracing::{event, instrument, Level};
+
+use crate::{
+    db,
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{
+    probe::{is_audio_file, probe},
+    split_duration,
+};
+
+/// Number of results `library search` shows
+const SEARCH_RESULTS: i64 = 10;
+
+/// Whether a scan is running, two would probe every file twice
+static SCANNING: AtomicBool = AtomicBool::new(false);
+
+/// A file in the local library
+#[derive(Clone, Debug)]
+pub struct LibraryTrack {
+    pub id: i32,
+    pub path: String,
+    pub title: String,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+impl From<tokio_postgres::Row> for LibraryTrack {
+    fn from(row: tokio_postgres::Row) -> Self {
+        Self {
+            id: row.get("id"),
+            path: row.get("path"),
+            title: row.get("title"),
+            artist: row.get("artist"),
+            album: row.get("album"),
+            duration: row
+                .get::<_, Option<i64>>("duration_ms")
+                .map(|ms| Duration::from_millis(ms as u64)),
+        }
+    }
+}
+
+/// The library directory, set through `LIBRARY_DIR`
+pub fn library_dir() -> SunnyResult<PathBuf> {
+    let dir = env::var("LIBRARY_DIR")
+        .map_err(|_| SunnyError::user("There's no local library set up (`LIBRARY_DIR`)"))?;
+
+    Path::new(&dir).canonicalize().map_err(|e| {
+        SunnyError::user_and_log(
+            "The local library can't be found",
+            format!("Invalid LIBRARY_DIR {}: {}", dir, e).as_str(),
+        )
+    })
+}
+
+/// Resolves `path` if it's a file inside the library, anything outside of it
+/// is never played as a local file
+pub fn in_library(path: &str) -> Option<PathBuf> {
+    let dir = library_dir().ok()?;
+    let path = Path::new(path).canonicalize().ok()?;
+
+    (path.starts_with(&dir) && path.is_file()).then_some(path)
+}
+
+/// Title of a file without a title tag
+pub fn file_title(path: &Path) -> String {
+    path.file_stem().map_or_else(
+        || "Unknown Title".to_string(),
+        |s| s.to_string_lossy().to_string(),
+    )
+}
+
+/// Lists the audio files in `dir` and its subdirectories. A subdirectory that
+/// can't be read is skipped, `dir` itself has to be readable.
+fn audio_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for path in fs::read_dir(dir)?.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.is_dir() {
+            if let Err(e) = audio_files(&path, files) {
+                event!(Level::WARN, %e, ?path, "unable to read library directory");
+            }
+        } else if is_audio_file(&path) {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether an indexed file was deleted, a file that can't be reached right
+/// now keeps its place in the index
+fn is_gone(path: &str) -> bool {
+    matches!(fs::metadata(path), Err(e) if e.kind() == io::ErrorKind::NotFound)
+}
+
+/// Marks the library as being scanned until dropped
+struct Scanning;
+
+impl Scanning {
+    fn start() -> SunnyResult<Self> {
+        if SCANNING.swap(true, Ordering::SeqCst) {
+            return Err(SunnyError::user("The library is already being scanned"));
+        }
+
+        Ok(Self)
+    }
+}
+
+impl Drop for Scanning {
+    fn drop(&mut self) {
+        SCANNING.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Scans the library directory into the index, removing files that are gone.
+/// Files that can't be probed keep what the index knew about them.
+///
+/// Returns the number of indexed files.
+#[instrument]
+pub async fn scan() -> SunnyResult<usize> {
+    let _scanning = Scanning::start()?;
+    let dir = library_dir()?;
+
+    let files = tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        audio_files(&dir, &mut files).map(|_| files)
+    })
+    .await
+    .map_err(|e| SunnyError::log(format!("Library scan panicked: {}", e).as_str()))?
+    .map_err(|e| {
+        SunnyError::user_and_log(
+            "The local library can't be read",
+            format!("Unable to read LIBRARY_DIR: {}", e).as_str(),
+        )
+    })?;
+
+    let client = db::connect().await?;
+
+    let mut paths = Vec::with_capacity(files.len());
+    for file in files {
+        let path = file.to_string_lossy().to_string();
+
+        let tags = match probe(&path).await {
+            Ok(tags) => tags,
+            Err(e) => {
+                event!(Level::WARN, %e, %path, "unable to read tags, skipping file");
+                continue;
+            }
+        };
+
+        client
+            .execute(
+                "INSERT INTO library_tracks (path, title, artist, album, duration_ms) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (path) DO UPDATE SET title = $2, artist = $3, album = $4, duration_ms = $5, scanned_at = now()",
+                &[
+                    &path,
+                    &tags.title.unwrap_or_else(|| file_title(&file)),
+                    &tags.artist,
+                    &tags.album,
+                    &tags.duration.map(|d| d.as_millis() as i64),
+                ],
+            )
+            .await
+            .map_err(db::log_err)?;
+
+        paths.push(path);
+    }
+
+    let gone: Vec<String> = client
+        .query(
+            "SELECT path FROM library_tracks WHERE path <> ALL($1)",
+            &[&paths],
+        )
+        .await
+        .map_err(db::log_err)?
+        .into_iter()
+        .map(|row| row.get::<_, String>("path"))
+        .filter(|path| is_gone(path))
+        .collect();
+
+    client
+        .execute("DELETE FROM library_tracks WHERE path = ANY($1)", &[&gone])
+        .await
+        .map_err(db::log_err)?;
+
+    Ok(paths.len())
+}
+
+/// Escapes the wildcards of a `LIKE` pattern, so `text` only matches itself
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Searches titles, artists, albums and paths of the library
+#[instrument]
+pub async fn search(text: &str) -> SunnyResult<Vec<LibraryTrack>> {
+    let text = escape_like(text);
+
+    Ok(db::connect()
+        .await?
+        .query(
+            "SELECT id, path, title, artist, album, duration_ms FROM library_tracks WHERE title ILIKE '%' || $1 || '%' ESCAPE '\\' OR artist ILIKE '%' || $1 || '%' ESCAPE '\\' OR album ILIKE '%' || $1 || '%' ESCAPE '\\' OR path ILIKE '%' || $1 || '%' ESCAPE '\\' ORDER BY artist, album, title LIMIT $2",
+            &[&text, &SEARCH_RESULTS],
+        )
+        .await
+        .map_err(db::log_err)?
+        .into_iter()
+        .map(LibraryTrack::from)
+        .collect())
+}
+
+/// Gets a track from the library by id
+#[instrument]
+pub async fn get(id: i32) -> SunnyResult<LibraryTrack> {
+    db::connect()
+        .await?
+        .query_opt(
+            "SELECT id, path, title, artist, album, duration_ms FROM library_tracks WHERE id = $1",
+            &[&id],
+        )
+        .await
+        .map_err(db::log_err)?
+        .map(LibraryTrack::from)
+        .ok_or_else(|| SunnyError::user(format!("There's no song {} in the library", id).as_str()))
+}
+
+/// Generates an embed listing search results
+pub fn generate_embed(query: &str, tracks: &[LibraryTrack]) -> CreateEmbed {
+    let mut e = CreateEmbed::default();
+    e.author(|a| a.name(format!("Library: {}", query)));
+
+    let lines: Vec<String> = tracks
+        .iter()
+        .map(|t| {
+            let artist = t.artist.as_deref().unwrap_or("Unknown Artist");
+            let album = t
+                .album
+                .as_ref()
+                .map(|a| format!(" ({})", a))
+                .unwrap_or_default();
+            let (min, sec) = split_duration(t.duration.unwrap_or_default());
+
+            format!(
+                "`{}` **{}** by {}{} [{}:{:02}]",
+                t.id, t.title, artist, album, min, sec
+            )
+        })
+        .collect();
+
+    if lines.is_empty() {
+        e.description("Nothing found");
+    } else {
+        e.description(lines.join("\n"));
+    }
+
+    e.footer(|f| f.text("Play one with `library play <id>`"));
+
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_like_wildcards() {
+        assert_eq!(escape_like("100% a_b"), "100\\% a\\_b");
+        assert_eq!(escape_like("c:\\music"), "c:\\\\music");
+        assert_eq!(escape_like("plain"), "plain");
+    }
+}
@@ -0,0 +1,246 @@
+use std::{collections::HashSet, process::Stdio, sync::Mutex};
+
+use once_cell::sync::Lazy;
+use serde_json::Value;
+use serenity::{client::Context, model::id::GuildId};
+use tokio::{process::Command, sync::Semaphore};
+use tracing::{event, instrument, Level};
+
+use crate::{
+    db,
+    structs::with_guild_state,
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::source::{restart_current, SourceKind, YTDL_ARGS};
+
+/// Target `loudness on` uses, about what streaming services normalize to
+pub const DEFAULT_TARGET: f64 = -16.0;
+
+/// Targets `loudness` accepts, the range ffmpeg's loudnorm supports
+pub const TARGET_RANGE: std::ops::RangeInclusive<f64> = -70.0..=-5.0;
+
+/// Maximum true peak (dBTP) of normalized tracks
+const TRUE_PEAK: f64 = -1.5;
+
+/// Loudness range (LU) loudnorm aims for
+const LOUDNESS_RANGE: f64 = 11.0;
+
+/// Measuring means streaming the whole source, so only a few at a time
+const MAX_MEASUREMENTS: usize = 2;
+
+static MEASUREMENT_PERMITS: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(MAX_MEASUREMENTS));
+
+/// Sources being measured right now, so they aren't measured twice
+static MEASURING: Lazy<Mutex<HashSet<String>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// Loudness of a source as measured by ffmpeg's loudnorm filter
+#[derive(Clone, Copy, Debug)]
+pub struct Loudness {
+    /// Integrated loudness, LUFS
+    pub integrated: f64,
+    /// True peak, dBTP
+    pub true_peak: f64,
+    /// Loudness range, LU
+    pub range: f64,
+    /// Gating threshold, LUFS
+    pub threshold: f64,
+}
+
+impl From<tokio_postgres::Row> for Loudness {
+    fn from(row: tokio_postgres::Row) -> Self {
+        Self {
+            integrated: row.get("integrated"),
+            true_peak: row.get("true_peak"),
+            range: row.get("range"),
+            threshold: row.get("threshold"),
+        }
+    }
+}
+
+impl Loudness {
+    /// Reads the json loudnorm prints at the end of its output
+    fn parse(output: &str) -> Option<Self> {
+        let json = &output[output.rfind('{')?..];
+        let value: Value = serde_json::from_str(json.get(..=json.find('}')?)?).ok()?;
+
+        // Every value is a string, silence measures as -inf
+        let field = |name: &str| {
+            value[name]
+                .as_str()?
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite())
+        };
+
+        Some(Self {
+            integrated: field("input_i")?,
+            true_peak: field("input_tp")?,
+            range: field("input_lra")?,
+            threshold: field("input_thresh")?,
+        })
+    }
+}
+
+/// The loudnorm filter bringing a source to `target`.
+///
+/// With a measurement it's a constant gain (as long as the true peak allows
+/// it), without one loudnorm adjusts on the fly.
+pub fn ffmpeg_filter(target: f64, measured: Option<Loudness>) -> String {
+    let filter = format!(
+        "loudnorm=I={}:TP={}:LRA={}",
+        target, TRUE_PEAK, LOUDNESS_RANGE
+    );
+
+    match measured {
+        Some(m) => format!(
+            "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:linear=true",
+            filter, m.integrated, m.true_peak, m.range, m.threshold
+        ),
+        None => filter,
+    }
+}
+
+/// Gets the cached measurement of a source
+#[instrument]
+pub async fn cached(uri: &str) -> SunnyResult<Option<Loudness>> {
+    Ok(db::connect()
+        .await?
+        .query_opt(
+            "SELECT integrated, true_peak, range, threshold FROM loudness_measurements WHERE url = $1",
+            &[&uri],
+        )
+        .await
+        .map_err(db::log_err)?
+        .map(Loudness::from))
+}
+
+/// Runs a source through loudnorm without playing it
+#[instrument]
+async fn measure(kind: SourceKind, uri: &str) -> SunnyResult<Loudness> {
+    let mut ffmpeg = Command::new("ffmpeg");
+    ffmpeg.args(["-hide_banner", "-nostats", "-i"]);
+
+    let mut ytdl = match kind {
+        SourceKind::File => {
+            ffmpeg.arg(uri).stdin(Stdio::null());
+            None
+        }
+        SourceKind::Ytdl => {
+            let mut ytdl = Command::new("youtube-dl")
+                .args(YTDL_ARGS)
+                .args(["-o", "-", "--", uri])
+                .stdin(Stdio::null())
+                .stderr(Stdio::null())
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .map_err(|e| SunnyError::log(format!("Failed to run ytdl: {}", e).as_str()))?;
+
+            let stdout: Stdio = ytdl
+                .stdout
+                .take()
+                .and_then(|s| s.try_into().ok())
+                .ok_or_else(|| SunnyError::log("Couldn't pipe ytdl into ffmpeg"))?;
+
+            ffmpeg.arg("-").stdin(stdout);
+            Some(ytdl)
+        }
+    };
+
+    let output = ffmpeg
+        .args([
+            "-vn",
+            "-af",
+            "loudnorm=print_format=json",
+            "-f",
+            "null",
+            "-",
+        ])
+        .stdout(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| SunnyError::log(format!("Failed to run ffmpeg: {}", e).as_str()))?;
+
+    if let Some(ytdl) = &mut ytdl {
+        // It's done once ffmpeg read everything, this just reaps it
+        let _ = ytdl.wait().await;
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(SunnyError::log(
+            format!("ffmpeg failed to measure {}: {}", uri, stderr.trim()).as_str(),
+        ));
+    }
+
+    Loudness::parse(&stderr)
+        .ok_or_else(|| SunnyError::log(format!("No loudness measured for {}", uri).as_str()))
+}
+
+/// Measures and caches the loudness of a source unless that's already
+/// cached or in progress
+async fn measure_and_cache(kind: SourceKind, uri: &str) -> SunnyResult<()> {
+    let _permit = MEASUREMENT_PERMITS
+        .acquire()
+        .await
+        .map_err(|e| SunnyError::log(format!("Measurement permits closed: {}", e).as_str()))?;
+
+    if cached(uri).await?.is_some() {
+        return Ok(());
+    }
+
+    let m = measure(kind, uri).await?;
+
+    db::connect()
+        .await?
+        .execute(
+            "INSERT INTO loudness_measurements (url, integrated, true_peak, range, threshold) VALUES ($1, $2, $3, $4, $5) ON CONFLICT (url) DO UPDATE SET integrated = $2, true_peak = $3, range = $4, threshold = $5, measured_at = now()",
+            &[&uri, &m.integrated, &m.true_peak, &m.range, &m.threshold],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    event!(Level::INFO, %uri, integrated = m.integrated, "measured loudness");
+
+    Ok(())
+}
+
+/// Measures the loudness of a source in the background, until then it's
+/// normalized on the fly
+pub fn measure_in_background(kind: SourceKind, uri: String) {
+    if let Ok(mut measuring) = MEASURING.lock() {
+        if !measuring.insert(uri.clone()) {
+            return;
+        }
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = measure_and_cache(kind, &uri).await {
+            event!(Level::WARN, %e, %uri, "unable to measure loudness");
+        }
+
+        if let Ok(mut measuring) = MEASURING.lock() {
+            measuring.remove(&uri);
+        }
+    });
+}
+
+/// Sets the loudness the guild's tracks are normalized to, or turns
+/// normalization off with `None`.
+///
+/// Like filters it applies to the current track right away.
+#[instrument(skip(ctx))]
+pub async fn set_loudness(
+    ctx: &Context,
+    guild_id: GuildId,
+    target: Option<f64>,
+) -> SunnyResult<()> {
+    let audio = with_guild_state(ctx, guild_id, |s| s.audio.clone()).await?;
+    audio
+        .write()
+        .map_err(|_| SunnyError::log("Audio settings lock poisoned"))?
+        .loudness_target = target;
+
+    restart_current(ctx, guild_id).await
+}
@@ -0,0 +1,499 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serenity::{
+    builder::CreateEmbed,
+    client::Context,
+    model::{channel::Attachment, id::GuildId},
+};
+use songbird::{
+    create_player,
+    input::{Input, Restartable},
+    tracks::{LoopState, TrackHandle},
+};
+use tracing::{event, instrument, Level};
+
+use crate::{
+    structs::{with_guild_state, Layer, LayerState, LayerTrack},
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{
+    fade, get_title,
+    probe::{is_audio_file, AUDIO_EXTENSIONS},
+    queue,
+    source_error::source_error,
+};
+
+/// Highest volume `<layer> volume` accepts, in percent
+pub const MAX_VOLUME: u8 = 100;
+
+/// How long the old ambience fades out when another one starts
+const SWAP_FADE: Duration = Duration::from_secs(2);
+
+/// Largest clip that can be uploaded
+pub const MAX_CLIP_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Longest name a clip can be uploaded as
+const MAX_CLIP_NAME: usize = 32;
+
+/// Fraction of its volume the music ducks to under soundboard clips
+const DUCK_VOLUME: f32 = 0.3;
+
+/// How long ducking the music takes, and bringing it back up
+const DUCK_FADE: Duration = Duration::from_millis(300);
+
+/// Time between volume changes while ducking the music
+const DUCK_STEP: Duration = Duration::from_millis(50);
+
+/// How often a clip the music ducks under is checked for having ended
+const DUCK_POLL: Duration = Duration::from_millis(250);
+
+/// The sounds directory, set through `SOUNDS_DIR`. Clips of a single guild
+/// go in a subdirectory named after its id.
+pub fn sounds_dir() -> SunnyResult<PathBuf> {
+    let dir = env::var("SOUNDS_DIR")
+        .map_err(|_| SunnyError::user("There's no sounds directory set up (`SOUNDS_DIR`)"))?;
+
+    Path::new(&dir).canonicalize().map_err(|e| {
+        SunnyError::user_and_log(
+            "The sounds directory can't be found",
+            format!("Invalid SOUNDS_DIR {}: {}", dir, e).as_str(),
+        )
+    })
+}
+
+/// Name of a clip, its file name without the extension
+fn clip_name(path: &Path) -> Option<String> {
+    path.file_stem().map(|s| s.to_string_lossy().to_string())
+}
+
+/// Lists the clips of a guild by name, the guild's own clips replace shared
+/// ones with the same name
+pub fn clips(guild_id: GuildId) -> SunnyResult<Vec<(String, PathBuf)>> {
+    let dir = sounds_dir()?;
+
+    let mut clips: Vec<(String, PathBuf)> = Vec::new();
+    for dir in [dir.join(guild_id.to_string()), dir] {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            let name = match clip_name(&path) {
+                Some(name) if is_audio_file(&path) => name,
+                _ => continue,
+            };
+
+            if !clips.iter().any(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+                clips.push((name, path));
+            }
+        }
+    }
+
+    clips.sort_by_key(|(name, _)| name.to_lowercase());
+
+    Ok(clips)
+}
+
+/// Finds a clip by name, with or without its extension
+pub fn find_clip(guild_id: GuildId, name: &str) -> SunnyResult<(String, PathBuf)> {
+    let stem = clip_name(Path::new(name)).unwrap_or_else(|| name.to_string());
+
+    clips(guild_id)?
+        .into_iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(&stem))
+        .ok_or_else(|| SunnyError::user(format!("There's no clip called `{}`", name).as_str()))
+}
+
+/// Plays a clip on the ambience or sfx layer. A new ambience replaces the
+/// old one, sound effects play over each other.
+///
+/// Returns the name of the clip and its track.
+#[instrument(skip(ctx))]
+pub async fn play_clip(
+    ctx: &Context,
+    guild_id: GuildId,
+    layer: Layer,
+    name: &str,
+) -> SunnyResult<(String, TrackHandle)> {
+    if layer == Layer::Music {
+        return Err(SunnyError::log("Clips don't play on the music layer"));
+    }
+
+    let (name, path) = find_clip(guild_id, name)?;
+    let path = path.to_string_lossy().to_string();
+
+    let mut source: Input = Restartable::ffmpeg(path.clone(), false)
+        .await
+        .map_err(|e| source_error(&path, &e))?
+        .into();
+    source.metadata.track = Some(name.clone());
+
+    let (mut track, handle) = create_player(source);
+
+    let LayerState {
+        volume, looping, ..
+    } = with_guild_state(ctx, guild_id, |s| s.layer(layer).clone()).await?;
+
+    track.set_volume(volume);
+    if looping {
+        track
+            .set_loops(LoopState::Infinite)
+            .map_err(|e| SunnyError::log(format!("Failed to loop clip: {}", e).as_str()))?;
+    }
+
+    handle.typemap().write().await.insert::<LayerTrack>(layer);
+
+    songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::user("I'm not in a voice channel"))?
+        .lock()
+        .await
+        .play(track);
+
+    let replaced = with_guild_state(ctx, guild_id, |s| {
+        let tracks = &mut s.layer(layer).tracks;
+        let replaced = if layer == Layer::Ambience {
+            std::mem::take(tracks)
+        } else {
+            Vec::new()
+        };
+
+        tracks.push(handle.clone());
+        replaced
+    })
+    .await?;
+
+    for old in replaced {
+        tokio::spawn(async move {
+            fade::ramp(old.clone(), volume, 0.0, SWAP_FADE).await;
+            let _ = old.stop();
+        });
+    }
+
+    Ok((name, handle))
+}
+
+/// Saves an uploaded clip in the guild's directory as `name`, or under its
+/// file name. A clip of the guild with the same name is replaced.
+///
+/// Returns the name of the clip.
+#[instrument(skip(attachment))]
+pub async fn add_clip(
+    guild_id: GuildId,
+    name: Option<String>,
+    attachment: &Attachment,
+) -> SunnyResult<String> {
+    let file = Path::new(&attachment.filename);
+    if !is_audio_file(file) {
+        return Err(SunnyError::user(
+            format!("Clips have to be one of: {}", AUDIO_EXTENSIONS.join(", ")).as_str(),
+        ));
+    }
+
+    if attachment.size > MAX_CLIP_SIZE {
+        return Err(SunnyError::user("That clip is too big"));
+    }
+
+    let name = name
+        .or_else(|| clip_name(file))
+        .filter(|n| {
+            !n.is_empty()
+                && n.len() <= MAX_CLIP_NAME
+                && n.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        })
+        .ok_or_else(|| {
+            SunnyError::user(
+                format!(
+                    "Clip names can only have letters, numbers, `_` and `-` and be up to {} long",
+                    MAX_CLIP_NAME
+                )
+                .as_str(),
+            )
+        })?;
+
+    let bytes = attachment.download().await.map_err(|e| {
+        SunnyError::user_and_log(
+            "Couldn't download that clip",
+            format!("Failed to download clip: {}", e).as_str(),
+        )
+    })?;
+
+    let dir = sounds_dir()?.join(guild_id.to_string());
+    let io_err =
+        |e: std::io::Error| SunnyError::log(format!("Failed to save clip: {}", e).as_str());
+
+    tokio::fs::create_dir_all(&dir).await.map_err(io_err)?;
+
+    // Replace the old file, even if it has another extension
+    if let Some(old) = guild_clip(&dir, &name) {
+        tokio::fs::remove_file(old).await.map_err(io_err)?;
+    }
+
+    let extension = file
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    tokio::fs::write(dir.join(format!("{}.{}", name, extension)), bytes)
+        .await
+        .map_err(io_err)?;
+
+    Ok(name)
+}
+
+/// Finds a clip uploaded in a guild, `dir` being the guild's directory
+fn guild_clip(dir: &Path, name: &str) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .find(|p| {
+            is_audio_file(p) && matches!(clip_name(p), Some(n) if n.eq_ignore_ascii_case(name))
+        })
+}
+
+/// Deletes a clip uploaded in a guild, shared clips can't be removed
+#[instrument]
+pub async fn remove_clip(guild_id: GuildId, name: &str) -> SunnyResult<()> {
+    let dir = sounds_dir()?.join(guild_id.to_string());
+
+    let path = guild_clip(&dir, name).ok_or_else(|| {
+        SunnyError::user(format!("There's no clip called `{}` uploaded here", name).as_str())
+    })?;
+
+    tokio::fs::remove_file(path)
+        .await
+        .map_err(|e| SunnyError::log(format!("Failed to remove clip: {}", e).as_str()))
+}
+
+/// Ducks the music while `clip` plays, overlapping clips keep it ducked
+/// until the last one is done
+#[instrument(skip(ctx))]
+pub async fn duck_under(ctx: &Context, guild_id: GuildId, clip: TrackHandle) -> SunnyResult<()> {
+    let first = with_guild_state(ctx, guild_id, |s| {
+        s.ducking += 1;
+        s.ducking == 1
+    })
+    .await?;
+
+    if first {
+        ramp_duck(ctx, guild_id, true).await?;
+    }
+
+    while clip.get_info().await.is_ok() {
+        tokio::time::sleep(DUCK_POLL).await;
+    }
+
+    let last = with_guild_state(ctx, guild_id, |s| {
+        s.ducking = s.ducking.saturating_sub(1);
+        s.ducking == 0
+    })
+    .await?;
+
+    if last {
+        ramp_duck(ctx, guild_id, false).await?;
+    }
+
+    Ok(())
+}
+
+/// Ramps the music's duck factor down or back up over [`DUCK_FADE`], applying
+/// it to whatever songs are queued at each step. Gives up once clips start or
+/// stop playing again, the ramp that starts then takes over.
+async fn ramp_duck(ctx: &Context, guild_id: GuildId, ducked: bool) -> SunnyResult<()> {
+    let from = with_guild_state(ctx, guild_id, |s| s.duck.unwrap_or(1.0)).await?;
+    let to = if ducked { DUCK_VOLUME } else { 1.0 };
+    let steps = (DUCK_FADE.as_millis() / DUCK_STEP.as_millis()).max(1) as u32;
+
+    for step in 1..=steps {
+        let factor = from + (to - from) * step as f32 / steps as f32;
+
+        let volume = with_guild_state(ctx, guild_id, |s| {
+            if (s.ducking > 0) != ducked {
+                return None;
+            }
+
+            s.duck = if step == steps && !ducked {
+                None
+            } else {
+                Some(factor)
+            };
+            Some(s.music_volume())
+        })
+        .await?;
+
+        let volume = match volume {
+            Some(volume) => volume,
+            None => break,
+        };
+
+        for track in layer_tracks(ctx, guild_id, Layer::Music).await? {
+            let _ = track.set_volume(volume);
+        }
+
+        tokio::time::sleep(DUCK_STEP).await;
+    }
+
+    Ok(())
+}
+
+/// Tracks playing on a layer, the queue for music
+async fn layer_tracks(
+    ctx: &Context,
+    guild_id: GuildId,
+    layer: Layer,
+) -> SunnyResult<Vec<TrackHandle>> {
+    if layer != Layer::Music {
+        return with_guild_state(ctx, guild_id, |s| s.layer(layer).tracks.clone()).await;
+    }
+
+    Ok(songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?
+        .lock()
+        .await
+        .queue()
+        .current_queue())
+}
+
+/// Sets the volume of a layer, 1.0 being the volume of the source
+#[instrument(skip(ctx))]
+pub async fn set_volume(
+    ctx: &Context,
+    guild_id: GuildId,
+    layer: Layer,
+    volume: f32,
+) -> SunnyResult<()> {
+    let volume = with_guild_state(ctx, guild_id, |s| {
+        s.layer(layer).volume = volume;
+
+        if layer == Layer::Music {
+            s.music_volume()
+        } else {
+            volume
+        }
+    })
+    .await?;
+
+    for track in layer_tracks(ctx, guild_id, layer).await? {
+        // Tracks that just ended are fine to miss
+        let _ = track.set_volume(volume);
+    }
+
+    Ok(())
+}
+
+/// Makes the tracks of a layer loop or not. For music that's just the current
+/// song, the rest of the queue plays as usual once it's skipped.
+#[instrument(skip(ctx))]
+pub async fn set_looping(
+    ctx: &Context,
+    guild_id: GuildId,
+    layer: Layer,
+    looping: bool,
+) -> SunnyResult<()> {
+    let tracks = if layer == Layer::Music {
+        let current = layer_tracks(ctx, guild_id, layer)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| SunnyError::user("No song playing"))?;
+
+        vec![current]
+    } else {
+        with_guild_state(ctx, guild_id, |s| s.layer(layer).tracks.clone()).await?
+    };
+
+    with_guild_state(ctx, guild_id, |s| s.layer(layer).looping = looping).await?;
+
+    for track in tracks {
+        let res = if looping {
+            track.enable_loop()
+        } else {
+            track.disable_loop()
+        };
+
+        if let Err(e) = res {
+            event!(Level::INFO, %e, layer = layer.name(), "unable to change looping");
+        }
+    }
+
+    Ok(())
+}
+
+/// Stops everything playing on a layer, for music that's `stop`
+#[instrument(skip(ctx))]
+pub async fn stop_layer(ctx: &Context, guild_id: GuildId, layer: Layer) -> SunnyResult<()> {
+    if layer == Layer::Music {
+        return queue::stop(ctx, guild_id).await;
+    }
+
+    let tracks = with_guild_state(ctx, guild_id, |s| {
+        std::mem::take(&mut s.layer(layer).tracks)
+    })
+    .await?;
+
+    for track in tracks {
+        let _ = track.stop();
+    }
+
+    Ok(())
+}
+
+/// Gets the state of every layer
+pub async fn layers(ctx: &Context, guild_id: GuildId) -> SunnyResult<Vec<(Layer, LayerState)>> {
+    with_guild_state(ctx, guild_id, |s| {
+        Layer::ALL
+            .iter()
+            .map(|l| (*l, s.layer(*l).clone()))
+            .collect()
+    })
+    .await
+}
+
+/// Generates an embed showing the layers of the mixer
+pub fn generate_embed(layers: &[(Layer, LayerState)]) -> CreateEmbed {
+    let mut e = CreateEmbed::default();
+    e.author(|a| a.name("Mixer"));
+
+    for (layer, state) in layers {
+        let playing = if *layer == Layer::Music {
+            "The queue".to_string()
+        } else if state.tracks.is_empty() {
+            "Nothing".to_string()
+        } else {
+            state
+                .tracks
+                .iter()
+                .map(|t| get_title(t.metadata()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let looping = if state.looping { " :repeat:" } else { "" };
+
+        e.field(
+            layer.name(),
+            format!(
+                "{}\n**Volume:** {}%{}",
+                playing,
+                (state.volume * 100.0).round(),
+                looping
+            ),
+            true,
+        );
+    }
+
+    e
+}
@@ -2,19 +2,73 @@
 //! Effects contains the main functionality of Sunny
 //!
 
+mod autoplay;
 mod deafen;
 pub mod display_queue;
+pub mod fade;
+mod filter;
+pub mod history;
 mod join;
 mod leave;
+pub mod library;
+pub mod loudness;
+pub mod mixer;
 pub mod now_playing;
+mod persist;
+pub mod playlist;
+mod probe;
 pub mod queue;
+mod queue_file;
+pub mod scene;
+pub mod soundboard;
+mod source;
+mod source_error;
+pub mod track_error;
+mod vote_skip;
 
+pub use autoplay::{autoplay, set_autoplay};
 pub use deafen::deafen;
+pub use filter::set_filter;
 pub use join::join;
 pub use leave::leave;
+pub use persist::{restore_queues, save_queues};
+pub use queue_file::{export_queue, import_queue, QueueFormat};
+pub use vote_skip::skip_or_vote;
 
-use songbird::input::Metadata;
+use serenity::{
+    client::Context,
+    model::{
+        id::GuildId,
+        interactions::{
+            message_component::MessageComponentInteraction,
+            InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
+        },
+    },
+};
+use songbird::{input::Metadata, tracks::TrackHandle};
 use std::time::Duration;
+use tracing::instrument;
+use url::Url;
+
+use crate::{
+    structs::{with_guild_state, TrackRecord},
+    utils::{SunnyError, SunnyResult},
+};
+
+use source::end_position;
+
+/// Checks that `url` parses as a url, so it can't be mistaken for an option
+/// by ytdl. Discord's `<>` around links are dropped.
+pub fn parse_url(url: &str) -> Option<String> {
+    let url = url
+        .strip_prefix('<')
+        .and_then(|u| u.strip_suffix('>'))
+        .unwrap_or(url);
+
+    Url::parse(url).ok()?;
+
+    Some(url.to_string())
+}
 
 /// `split_duration` splits a [`Duration`] into a (minutes, seconds) tuple
 const fn split_duration(d: Duration) -> (u64, u64) {
@@ -24,14 +78,14 @@ const fn split_duration(d: Duration) -> (u64, u64) {
 fn get_title(m: &Metadata) -> &str {
     m.track
         .as_deref()
-        .or_else(|| m.title.as_deref())
+        .or(m.title.as_deref())
         .unwrap_or("Unknown Title")
 }
 
 fn get_artist(m: &Metadata) -> &str {
     m.artist
         .as_deref()
-        .or_else(|| m.channel.as_deref())
+        .or(m.channel.as_deref())
         .unwrap_or("Unknown Artist")
 }
 
@@ -39,6 +93,111 @@ pub fn get_song(m: &Metadata) -> String {
     format!("{} by {}", get_title(m), get_artist(m))
 }
 
+/// How long each track of `queue` plays: what's left of the current one and
+/// the lengths of the others, at the filter's `tempo`. Streams and anything
+/// else without a length get `None`.
+async fn play_lengths(queue: &[TrackHandle], tempo: f64) -> Vec<Option<Duration>> {
+    let mut lengths = Vec::with_capacity(queue.len());
+
+    for (i, track) in queue.iter().enumerate() {
+        let length = if i == 0 {
+            let position = track
+                .get_info()
+                .await
+                .map(|i| i.position)
+                .unwrap_or_default();
+            end_position(track)
+                .await
+                .map(|end| end.saturating_sub(position))
+        } else {
+            track.metadata().duration.map(|d| d.div_f64(tempo))
+        };
+
+        lengths.push(length);
+    }
+
+    lengths
+}
+
+/// Estimates how long until each track starts from how long the tracks before
+/// it play, each one starting `crossfade` before the one before it ends.
+/// Tracks after one without a length get `None`.
+fn wait_times(lengths: &[Option<Duration>], crossfade: Duration) -> Vec<Option<Duration>> {
+    let mut wait = Some(Duration::ZERO);
+
+    lengths
+        .iter()
+        .map(|length| {
+            let starts_in = wait;
+            wait = wait
+                .zip(*length)
+                .map(|(w, l)| w + l.saturating_sub(crossfade));
+
+            starts_in
+        })
+        .collect()
+}
+
+/// Estimates how long until each track of `queue` starts, with the guild's
+/// filter and crossfade
+async fn queue_waits(
+    ctx: &Context,
+    guild_id: GuildId,
+    queue: &[TrackHandle],
+) -> SunnyResult<Vec<Option<Duration>>> {
+    let audio = with_guild_state(ctx, guild_id, |s| s.audio.clone()).await?;
+    let (tempo, crossfade) = audio
+        .read()
+        .map(|a| (a.tempo(), a.crossfade.unwrap_or_default()))
+        .unwrap_or((1.0, Duration::ZERO));
+
+    Ok(wait_times(&play_lengths(queue, tempo).await, crossfade))
+}
+
+/// Formats an estimated wait like `~12:34`
+pub fn format_wait(wait: Duration) -> String {
+    let (min, sec) = split_duration(wait);
+    format!("~{}:{:02}", min, sec)
+}
+
+/// Estimates how long until the track at `index` of the queue starts
+#[instrument(skip(ctx))]
+pub async fn wait_time(
+    ctx: &Context,
+    guild_id: GuildId,
+    index: usize,
+) -> SunnyResult<Option<Duration>> {
+    let queue = display_queue::get_queue(ctx, guild_id).await?;
+    let waits = queue_waits(ctx, guild_id, &queue).await?;
+
+    Ok(waits.get(index).copied().flatten())
+}
+
+/// Looks up Sunny's [`TrackRecord`] for each of the given tracks
+async fn get_records(
+    ctx: &Context,
+    guild_id: GuildId,
+    tracks: &[TrackHandle],
+) -> SunnyResult<Vec<Option<TrackRecord>>> {
+    with_guild_state(ctx, guild_id, |s| {
+        tracks
+            .iter()
+            .map(|t| s.records.get(&t.uuid()).cloned())
+            .collect()
+    })
+    .await
+}
+
+/// Describes who requested a track, when, and how
+fn requested_by(r: &TrackRecord) -> String {
+    format!(
+        "<@{}> via `{}` <t:{}:R>",
+        r.requester,
+        r.command,
+        r.enqueued_at.timestamp()
+    )
+}
+
 const fn string_or_default<'a>(s: &'a str, d: &'a str) -> &'a str {
     if s.is_empty() {
         d
@@ -46,3 +205,71 @@ const fn string_or_default<'a>(s: &'a str, d: &'a str) -> &'a str {
         s
     }
 }
+
+/// Replies to a component interaction so only the user who used it sees it
+async fn reply_ephemeral(
+    ctx: &Context,
+    mci: &MessageComponentInteraction,
+    content: &str,
+) -> SunnyResult<()> {
+    mci.create_interaction_response(&ctx.http, |cir| {
+        cir.kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|d| {
+                d.content(content)
+                    .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+            })
+    })
+    .await
+    .map_err(|e| {
+        SunnyError::log(format!("Unable to create interaction response: {:?}", e).as_str())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: u64) -> Duration {
+        Duration::from_secs(s)
+    }
+
+    #[test]
+    fn waits_add_up_lengths() {
+        let lengths = [Some(secs(30)), Some(secs(200)), Some(secs(100))];
+
+        assert_eq!(
+            wait_times(&lengths, Duration::ZERO),
+            vec![Some(secs(0)), Some(secs(30)), Some(secs(230))]
+        );
+    }
+
+    #[test]
+    fn waits_overlap_by_the_crossfade() {
+        let lengths = [Some(secs(30)), Some(secs(200)), Some(secs(100))];
+
+        assert_eq!(
+            wait_times(&lengths, secs(5)),
+            vec![Some(secs(0)), Some(secs(25)), Some(secs(220))]
+        );
+    }
+
+    #[test]
+    fn short_tracks_overlap_at_most_their_length() {
+        let lengths = [Some(secs(3)), Some(secs(200))];
+
+        assert_eq!(
+            wait_times(&lengths, secs(5)),
+            vec![Some(secs(0)), Some(secs(0))]
+        );
+    }
+
+    #[test]
+    fn waits_stop_after_a_stream() {
+        let lengths = [Some(secs(30)), None, Some(secs(100))];
+
+        assert_eq!(
+            wait_times(&lengths, Duration::ZERO),
+            vec![Some(secs(0)), Some(secs(30)), None]
+        );
+    }
+}
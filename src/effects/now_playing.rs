@@ -1,33 +1,209 @@
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use serenity::{
+    builder::{CreateActionRow, CreateEmbed},
     client::Context,
-    model::id::{ChannelId, GuildId},
+    futures::{future::BoxFuture, prelude::*},
+    model::{
+        channel::Message,
+        id::{ChannelId, GuildId, UserId},
+        interactions::{
+            message_component::{ButtonStyle, MessageComponentInteraction},
+            InteractionApplicationCommandCallbackDataFlags, InteractionResponseType,
+        },
+    },
+};
+use songbird::{
+    input::Metadata,
+    tracks::{PlayMode, TrackHandle},
+};
+use tracing::{event, instrument, Level};
+
+use crate::{
+    checks, emit,
+    structs::{
+        with_guild_state, Announce, CoverArt, FadingOut, FilterPreset, Layer, NowPlayingMessage,
+        TrackRecord,
+    },
+    utils::{SunnyError, SunnyResult},
 };
-use songbird::{input::Metadata, tracks::TrackHandle};
-use tracing::instrument;
 
-use crate::utils::{SunnyError, SunnyResult};
+use super::{
+    get_artist, get_records, get_song, get_title, history, mixer, queue, requested_by,
+    skip_or_vote, split_duration,
+};
 
-use super::{get_artist, get_title, split_duration};
+/// File name the cover art is attached as
+const COVER_FILE: &str = "cover.jpg";
 
-/// Generates an embed to show what's currently playing and what is up next
-pub fn generate_embed(
-    m: &Metadata,
-    pos: Duration,
-    m2: Option<&Metadata>,
-) -> serenity::builder::CreateEmbed {
-    let mut e = serenity::builder::CreateEmbed::default();
+/// How often the controller's progress bar moves
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Length of the progress bar
+const BAR_LEN: usize = 20;
+
+/// How much the volume buttons change the music volume by
+const VOLUME_STEP: f32 = 0.1;
+
+/// Buttons of the now playing controller
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Button {
+    PauseResume,
+    Skip,
+    Back,
+    Loop,
+    Shuffle,
+    VolumeDown,
+    VolumeUp,
+}
+
+impl Button {
+    const ALL: [Self; 7] = [
+        Self::PauseResume,
+        Self::Skip,
+        Self::Back,
+        Self::Loop,
+        Self::Shuffle,
+        Self::VolumeDown,
+        Self::VolumeUp,
+    ];
+
+    const fn id(self) -> &'static str {
+        match self {
+            Self::PauseResume => "np_pause",
+            Self::Skip => "np_skip",
+            Self::Back => "np_back",
+            Self::Loop => "np_loop",
+            Self::Shuffle => "np_shuffle",
+            Self::VolumeDown => "np_volume_down",
+            Self::VolumeUp => "np_volume_up",
+        }
+    }
+
+    fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|b| b.id() == id)
+    }
+
+    /// Does what the button's command does, behind the same checks. Only
+    /// `shuffle` works without being in the call.
+    async fn press(
+        self,
+        ctx: &Context,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> SunnyResult<()> {
+        if self != Self::Shuffle {
+            checks::in_same_voice(ctx, guild_id, user_id).await?;
+        }
+
+        match self {
+            Self::PauseResume => {
+                let (current, _) = get_songs(ctx, guild_id).await?;
+                let current = current.ok_or_else(|| SunnyError::user("No song playing"))?;
+
+                if is_paused(&current).await {
+                    queue::resume(ctx, guild_id).await
+                } else {
+                    queue::pause(ctx, guild_id).await
+                }
+            }
+            Self::Skip => skip_or_vote(ctx, guild_id, channel_id, user_id)
+                .await
+                .map(|_| ()),
+            Self::Back => history::back(ctx, guild_id, user_id).await.map(|_| ()),
+            Self::Loop => {
+                let looping =
+                    with_guild_state(ctx, guild_id, |s| s.layer(Layer::Music).looping).await?;
+
+                mixer::set_looping(ctx, guild_id, Layer::Music, !looping).await
+            }
+            Self::Shuffle => queue::shuffle(ctx, guild_id, None).await.map(|_| ()),
+            Self::VolumeDown | Self::VolumeUp => {
+                let volume =
+                    with_guild_state(ctx, guild_id, |s| s.layer(Layer::Music).volume).await?;
+
+                let step = if self == Self::VolumeUp {
+                    VOLUME_STEP
+                } else {
+                    -VOLUME_STEP
+                };
 
-    e.author(|a| a.name("Now Playing:"));
+                // Rounded so steps don't drift
+                let volume = ((volume + step) * 10.0).round() / 10.0;
 
-    let title = get_title(m);
+                mixer::set_volume(ctx, guild_id, Layer::Music, volume.clamp(0.0, max_volume()))
+                    .await
+            }
+        }
+    }
+}
 
-    let artist = get_artist(m);
+fn max_volume() -> f32 {
+    f32::from(mixer::MAX_VOLUME) / 100.0
+}
+
+/// Everything the controller shows
+struct Status {
+    current: TrackHandle,
+    position: Duration,
+    paused: bool,
+    next: Option<Metadata>,
+    record: Option<TrackRecord>,
+    filter: Option<FilterPreset>,
+    looping: bool,
+    volume: f32,
+    cover: Option<Arc<Vec<u8>>>,
+}
 
-    e.title(format!("{} by {}", title, artist));
+fn progress_bar(position: Duration, duration: Option<Duration>) -> String {
+    let (curr_min, curr_sec) = split_duration(position);
 
-    if let Some(thumbnail) = &m.thumbnail {
+    // Streams don't have an end to show progress towards
+    let duration = match duration.filter(|d| !d.is_zero()) {
+        Some(duration) => duration,
+        None => return format!("`{}:{:02}`", curr_min, curr_sec),
+    };
+
+    let (max_min, max_sec) = split_duration(duration);
+    let knob = (position.as_secs_f64() / duration.as_secs_f64() * BAR_LEN as f64) as usize;
+
+    let bar: String = (0..BAR_LEN)
+        .map(|i| {
+            if i == knob.min(BAR_LEN - 1) {
+                '🔘'
+            } else {
+                '▬'
+            }
+        })
+        .collect();
+
+    format!(
+        "`{}:{:02}` {} `{}:{:02}`",
+        curr_min, curr_sec, bar, max_min, max_sec
+    )
+}
+
+/// Generates an embed to show what's currently playing and what is up next
+fn generate_embed(status: &Status, with_cover: bool) -> CreateEmbed {
+    let mut e = CreateEmbed::default();
+
+    let m = status.current.metadata();
+
+    e.author(|a| {
+        a.name(if status.paused {
+            "Paused:"
+        } else {
+            "Now Playing:"
+        })
+    });
+
+    e.title(format!("{} by {}", get_title(m), get_artist(m)));
+
+    // Embedded cover art is attached to the message instead of linked
+    if with_cover {
+        e.thumbnail(format!("attachment://{}", COVER_FILE));
+    } else if let Some(thumbnail) = &m.thumbnail {
         e.thumbnail(thumbnail);
     }
 
@@ -35,23 +211,111 @@ pub fn generate_embed(
         e.url(url);
     }
 
-    let (curr_min, curr_sec) = split_duration(pos);
-    let (max_min, max_sec) = split_duration(m.duration.unwrap_or_default());
-    let progress = format!(
-        "**Current Time:** {}:{:02} / {}:{:02}",
-        curr_min, curr_sec, max_min, max_sec
+    let progress = progress_bar(status.position, m.duration);
+
+    let volume = format!(
+        "**Volume:** {}%{}",
+        (status.volume * 100.0).round(),
+        if status.looping { " :repeat:" } else { "" }
     );
 
-    let up_next = m2
+    let up_next = status
+        .next
+        .as_ref()
         .map(|m2| format!("**Up Next:** {} by {}", get_title(m2), get_artist(m2)))
         .unwrap_or_default();
 
-    e.description(&[progress, up_next].join("\n"));
+    let requested = status
+        .record
+        .as_ref()
+        .map(|r| format!("**Requested by:** {}", requested_by(r)))
+        .unwrap_or_default();
+
+    let filter = status
+        .filter
+        .map(|f| format!("**Filter:** {}", f.name()))
+        .unwrap_or_default();
+
+    e.description(
+        [progress, volume, requested, filter, up_next]
+            .iter()
+            .filter(|l| !l.is_empty())
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n"),
+    );
     e.timestamp(&chrono::Utc::now());
 
     e
 }
 
+fn button(row: &mut CreateActionRow, b: Button, label: &str, style: ButtonStyle, disabled: bool) {
+    row.create_button(|c| {
+        c.style(style);
+        c.label(label);
+        c.custom_id(b.id());
+        c.disabled(disabled)
+    });
+}
+
+fn build_action_rows(status: &Status) -> Vec<CreateActionRow> {
+    let mut playback = CreateActionRow::default();
+    button(
+        &mut playback,
+        Button::Back,
+        "Back",
+        ButtonStyle::Secondary,
+        false,
+    );
+
+    let (label, style) = if status.paused {
+        ("Resume", ButtonStyle::Success)
+    } else {
+        ("Pause", ButtonStyle::Primary)
+    };
+    button(&mut playback, Button::PauseResume, label, style, false);
+
+    button(
+        &mut playback,
+        Button::Skip,
+        "Skip",
+        ButtonStyle::Secondary,
+        false,
+    );
+
+    let mut options = CreateActionRow::default();
+    let style = if status.looping {
+        ButtonStyle::Success
+    } else {
+        ButtonStyle::Secondary
+    };
+    button(&mut options, Button::Loop, "Loop", style, false);
+
+    button(
+        &mut options,
+        Button::Shuffle,
+        "Shuffle",
+        ButtonStyle::Secondary,
+        status.next.is_none(),
+    );
+    button(
+        &mut options,
+        Button::VolumeDown,
+        "Volume -",
+        ButtonStyle::Secondary,
+        status.volume <= 0.0,
+    );
+    button(
+        &mut options,
+        Button::VolumeUp,
+        "Volume +",
+        ButtonStyle::Secondary,
+        status.volume >= max_volume(),
+    );
+
+    vec![playback, options]
+}
+
 /// Gets the current and next up song's [`TrackHandle`].
 #[instrument(skip(ctx))]
 async fn get_songs(
@@ -66,61 +330,372 @@ async fn get_songs(
         .get(guild_id)
         .ok_or_else(|| SunnyError::log("No Call"))?;
 
-    let call = call_m.lock().await;
+    let queue = call_m.lock().await.queue().current_queue();
+
+    // Once a track fades out the next one is the one people hear
+    let fading = match queue.first() {
+        Some(t) => t.typemap().read().await.contains_key::<FadingOut>(),
+        None => false,
+    };
+
+    let mut songs = queue.into_iter().skip(usize::from(fading));
+
+    Ok((songs.next(), songs.next()))
+}
 
-    Ok((
-        call.queue().current(),
-        call.queue().current_queue().get(1).cloned(),
-    ))
+async fn is_paused(track: &TrackHandle) -> bool {
+    matches!(track.get_info().await, Ok(info) if info.playing == PlayMode::Pause)
 }
 
-/// Sends a `now_playing` embed and updates the progress every 10 seconds
+/// Gets what the controller shows, `None` if nothing is playing
+async fn status(ctx: &Context, guild_id: GuildId) -> SunnyResult<Option<Status>> {
+    // Without a call nothing plays
+    let (current, next) = get_songs(ctx, guild_id).await.unwrap_or_default();
+
+    let current = match current {
+        Some(current) => current,
+        None => return Ok(None),
+    };
+
+    // Will error when finished
+    let info = match current.get_info().await {
+        Ok(info) => info,
+        Err(_) => return Ok(None),
+    };
+
+    let record = get_records(ctx, guild_id, std::slice::from_ref(&current))
+        .await?
+        .pop()
+        .flatten();
+
+    let cover = current.typemap().read().await.get::<CoverArt>().cloned();
+
+    let (audio, music) = with_guild_state(ctx, guild_id, |s| {
+        (s.audio.clone(), s.layer(Layer::Music).clone())
+    })
+    .await?;
+    let filter = audio.read().ok().and_then(|a| a.filter);
+
+    Ok(Some(Status {
+        current,
+        position: info.position,
+        paused: info.playing == PlayMode::Pause,
+        next: next.map(|t| t.metadata().clone()),
+        record,
+        filter,
+        looping: music.looping,
+        volume: music.volume,
+        cover,
+    }))
+}
+
+/// Sends a new now playing controller, replacing the guild's old one
 #[instrument(skip(ctx))]
 pub async fn send_embed(
     ctx: &Context,
     guild_id: GuildId,
     channel_id: ChannelId,
 ) -> SunnyResult<()> {
-    let (current, next) = get_songs(ctx, guild_id).await?;
+    let status = status(ctx, guild_id)
+        .await?
+        .ok_or_else(|| SunnyError::user("No song playing"))?;
 
-    let current = current.ok_or_else(|| SunnyError::user("No song playing"))?;
+    if let Some(old) = with_guild_state(ctx, guild_id, |s| s.now_playing.take()).await? {
+        old.channel_id
+            .delete_message(&ctx.http, old.message_id)
+            .await
+            .ok();
+    }
 
-    let position = current
-        .get_info()
+    post(ctx, guild_id, channel_id, status).await
+}
+
+/// Posts a controller and makes it the guild's, which the old one notices
+/// and stops on its own
+async fn post(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    status: Status,
+) -> SunnyResult<()> {
+    let message = channel_id
+        .send_message(&ctx.http, |m| {
+            if let Some(cover) = &status.cover {
+                m.add_file((cover.as_slice(), COVER_FILE));
+            }
+
+            m.components(|c| c.set_action_rows(build_action_rows(&status)));
+            m.set_embed(generate_embed(&status, status.cover.is_some()))
+        })
         .await
-        .map_err(|e| SunnyError::log(format!("a: {:?}", e).as_str()))? // * hackers on diefstal e(stradiol)n heling
-        .position;
+        .map_err(|e| SunnyError::log(format!("Sending message failed {:?}", e).as_str()))?;
 
-    let next_metadata = next.map(|t| t.metadata().clone());
+    let now_playing = NowPlayingMessage {
+        channel_id,
+        message_id: message.id,
+        track: status.current.uuid(),
+        cover_of: status.cover.as_ref().map(|_| status.current.uuid()),
+    };
+    with_guild_state(ctx, guild_id, |s| s.now_playing = Some(now_playing)).await?;
 
-    // e
-    let mut m = channel_id
-        .send_message(&ctx.http, |m| {
-            m.set_embed(generate_embed(
-                current.metadata(),
-                position,
-                next_metadata.as_ref(),
-            ))
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let res = await_interactions(&ctx, message, guild_id).await;
+        emit!(res, Level::WARN);
+    });
+
+    Ok(())
+}
+
+/// Announces what's playing now the way the guild wants it, in `channel_id`
+/// if there's no controller yet
+#[instrument(skip(ctx))]
+pub async fn update_embed(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> SunnyResult<()> {
+    let (announce, now_playing) =
+        with_guild_state(ctx, guild_id, |s| (s.announce, s.now_playing)).await?;
+
+    let now_playing = match now_playing {
+        Some(now_playing) => now_playing,
+        None if announce == Announce::Silent => return Ok(()),
+        None => return send_embed(ctx, guild_id, channel_id).await,
+    };
+
+    // Resuming plays the same track again, that's no new song to announce
+    let status = match status(ctx, guild_id).await? {
+        Some(status)
+            if announce == Announce::Every && status.current.uuid() != now_playing.track =>
+        {
+            status
+        }
+        _ => return refresh(ctx, guild_id, now_playing).await,
+    };
+
+    // Earlier announcements stay, without buttons that would be stale
+    now_playing
+        .channel_id
+        .edit_message(&ctx.http, now_playing.message_id, |m| m.components(|c| c))
+        .await
+        .ok();
+
+    post(ctx, guild_id, now_playing.channel_id, status).await
+}
+
+/// Sets how songs are announced, a silent guild's controller is removed
+#[instrument(skip(ctx))]
+pub async fn set_announce(ctx: &Context, guild_id: GuildId, announce: Announce) -> SunnyResult<()> {
+    let now_playing = with_guild_state(ctx, guild_id, |s| {
+        s.announce = announce;
+        s.now_playing
+    })
+    .await?;
+
+    match now_playing {
+        Some(now_playing) if announce == Announce::Silent => {
+            remove(ctx, guild_id, now_playing).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Sends a DM to the requester of a song that started, if they asked for one
+/// with `notify`. Resuming a paused song doesn't send another.
+#[instrument(skip(ctx))]
+pub async fn notify_requester(
+    ctx: &Context,
+    guild_id: GuildId,
+    voice_channel_id: ChannelId,
+    track: &TrackHandle,
+) -> SunnyResult<()> {
+    let uuid = track.uuid();
+
+    let requester = with_guild_state(ctx, guild_id, |s| {
+        let start_dms = &s.start_dms;
+
+        match s.records.get_mut(&uuid) {
+            Some(r) if !r.notified && !r.is_autoplay() && start_dms.contains(&r.requester) => {
+                r.notified = true;
+                Some(r.requester)
+            }
+            _ => None,
+        }
+    })
+    .await?;
+
+    let requester = match requester {
+        Some(requester) => requester,
+        None => return Ok(()),
+    };
+
+    requester
+        .create_dm_channel(&ctx.http)
+        .await
+        .map_err(|e| SunnyError::log(format!("Unable to open DM: {:?}", e).as_str()))?
+        .say(
+            &ctx.http,
+            format!(
+                "Your song {} is starting in <#{}>",
+                get_song(track.metadata()),
+                voice_channel_id
+            ),
+        )
+        .await
+        .map_err(|e| SunnyError::log(format!("Unable to send DM: {:?}", e).as_str()))?;
+
+    Ok(())
+}
+
+/// Edits the controller in place, or removes it once nothing is playing
+async fn refresh(
+    ctx: &Context,
+    guild_id: GuildId,
+    now_playing: NowPlayingMessage,
+) -> SunnyResult<()> {
+    let status = match status(ctx, guild_id).await? {
+        Some(status) => status,
+        None => return remove(ctx, guild_id, now_playing).await,
+    };
+
+    // Editing can't drop the cover art of an earlier track, a new controller
+    // replaces the message instead
+    if matches!(now_playing.cover_of, Some(uuid) if uuid != status.current.uuid()) {
+        return replace(ctx, guild_id, now_playing, status).await;
+    }
+
+    let with_cover = status.cover.is_some() && now_playing.cover_of.is_some();
+
+    let edited = now_playing
+        .channel_id
+        .edit_message(&ctx.http, now_playing.message_id, |m| {
+            m.components(|c| c.set_action_rows(build_action_rows(&status)));
+            m.set_embed(generate_embed(&status, with_cover))
         })
+        .await;
+
+    if let Err(e) = edited {
+        // Most likely deleted, the next track sends a new one
+        remove(ctx, guild_id, now_playing).await?;
+
+        return Err(SunnyError::log(
+            format!("Unable to edit now playing message: {:?}", e).as_str(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Swaps the controller for a new one in the same channel. Boxed, as the new
+/// controller's task refreshes it and could end up here again.
+fn replace(
+    ctx: &Context,
+    guild_id: GuildId,
+    now_playing: NowPlayingMessage,
+    status: Status,
+) -> BoxFuture<'_, SunnyResult<()>> {
+    Box::pin(async move {
+        remove(ctx, guild_id, now_playing).await?;
+        post(ctx, guild_id, now_playing.channel_id, status).await
+    })
+}
+
+/// Deletes the controller and forgets it, unless it was already replaced
+async fn remove(
+    ctx: &Context,
+    guild_id: GuildId,
+    now_playing: NowPlayingMessage,
+) -> SunnyResult<()> {
+    with_guild_state(ctx, guild_id, |s| {
+        if matches!(s.now_playing, Some(np) if np.message_id == now_playing.message_id) {
+            s.now_playing = None;
+        }
+    })
+    .await?;
+
+    now_playing
+        .channel_id
+        .delete_message(&ctx.http, now_playing.message_id)
         .await
-        .map_err(|estradiol| {
-            SunnyError::log(format!("Sending message failed {:?}", estradiol).as_str())
-        })?;
+        .ok();
 
-    let c = ctx.clone();
-    loop {
-        tokio::time::sleep(Duration::from_secs(10)).await;
+    Ok(())
+}
 
-        // Will error when finished
-        if let Ok(info) = current.get_info().await {
-            let embed = generate_embed(current.metadata(), info.position, next_metadata.as_ref());
+/// Handles a button press, telling only the presser what went wrong
+async fn handle_press(ctx: Context, guild_id: GuildId, mci: Arc<MessageComponentInteraction>) {
+    let button = match Button::from_id(&mci.data.custom_id) {
+        Some(button) => button,
+        None => return,
+    };
 
-            m.edit(&c.http, |e| e.set_embed(embed)).await.ok();
-        } else {
-            #[cfg_attr(not(feature = "url/cache"), allow(clippy::unwrap_used))]
-            m.delete(&c.http).await.unwrap();
-            break;
+    // Acknowledged first, skipping by vote and going back take a while
+    let acknowledged = mci
+        .create_interaction_response(&ctx.http, |cir| {
+            cir.kind(InteractionResponseType::DeferredUpdateMessage)
+        })
+        .await;
+
+    if let Err(e) = acknowledged {
+        event!(Level::WARN, ?e, "unable to acknowledge button press");
+        return;
+    }
+
+    let pressed = button
+        .press(&ctx, guild_id, mci.channel_id, mci.user.id)
+        .await;
+
+    if let Err(e) = &pressed {
+        let content = e.user_message().unwrap_or("Couldn't do that");
+
+        let res = mci
+            .create_followup_message(&ctx.http, |f| {
+                f.content(content)
+                    .flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL)
+            })
+            .await;
+
+        if let Err(e) = res {
+            event!(Level::WARN, ?e, "unable to reply to button press");
+        }
+    }
+
+    emit!(pressed, Level::INFO);
+
+    if let Ok(Some(now_playing)) = with_guild_state(&ctx, guild_id, |s| s.now_playing).await {
+        let res = refresh(&ctx, guild_id, now_playing).await;
+        emit!(res, Level::WARN);
+    }
+}
+
+/// Handles the buttons and moves the progress bar until the controller is
+/// replaced or removed
+async fn await_interactions(ctx: &Context, msg: Message, guild_id: GuildId) -> SunnyResult<()> {
+    let mut collector = msg.await_component_interactions(&ctx.shard).await;
+
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+    // The first tick is right away
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            mci = collector.next() => match mci {
+                Some(mci) => {
+                    tokio::spawn(handle_press(ctx.clone(), guild_id, mci));
+                    continue;
+                }
+                None => break,
+            },
+            _ = interval.tick() => {}
         }
+
+        let now_playing = match with_guild_state(ctx, guild_id, |s| s.now_playing).await? {
+            Some(np) if np.message_id == msg.id => np,
+            _ => break,
+        };
+
+        refresh(ctx, guild_id, now_playing).await?;
     }
 
     Ok(())
@@ -0,0 +1,269 @@
+use std::{collections::HashMap, time::Duration};
+
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, GuildId, UserId},
+    prelude::{RwLock, TypeMap},
+};
+use songbird::{tracks::TrackHandle, SongbirdKey};
+use tracing::{event, instrument, Level};
+use uuid::Uuid;
+
+use crate::{
+    db, emit,
+    structs::{ChannelBinding, EventConfig, GuildStates, TrackRecord},
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{
+    deafen, join,
+    queue::{self, EnqueueAt},
+    source::{position_at, source_time},
+};
+
+/// A queued track as it is stored in the database.
+#[derive(Debug)]
+struct SavedTrack {
+    url: String,
+    requester: Option<UserId>,
+    /// Playback position in the source, filters change how long that plays
+    offset: Duration,
+}
+
+/// Snapshots a queue, only the current track keeps its playback position.
+async fn snapshot(tracks: &[TrackHandle], requesters: &HashMap<Uuid, UserId>) -> Vec<SavedTrack> {
+    let mut saved = Vec::with_capacity(tracks.len());
+
+    for (i, track) in tracks.iter().enumerate() {
+        let url = if let Some(url) = &track.metadata().source_url {
+            url.clone()
+        } else {
+            event!(Level::WARN, uuid = %track.uuid(), "track without source url not saved");
+            continue;
+        };
+
+        let offset = if i == 0 {
+            let position = track
+                .get_info()
+                .await
+                .map(|info| info.position)
+                .unwrap_or_default();
+
+            source_time(track, position).await
+        } else {
+            Duration::default()
+        };
+
+        saved.push(SavedTrack {
+            url,
+            requester: requesters.get(&track.uuid()).copied(),
+            offset,
+        });
+    }
+
+    saved
+}
+
+/// Saves the queue and channel binding of every active call, so they can be
+/// restored by [`restore_queues`] after a restart.
+///
+/// Returns the number of saved queues.
+#[instrument(skip(data))]
+pub async fn save_queues(data: &RwLock<TypeMap>) -> SunnyResult<usize> {
+    let (songbird, states) = {
+        let data = data.read().await;
+
+        (
+            data.get::<SongbirdKey>()
+                .cloned()
+                .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?,
+            data.get::<GuildStates>()
+                .cloned()
+                .ok_or_else(|| SunnyError::log("Couldn't get guild states"))?,
+        )
+    };
+
+    let bindings: Vec<(GuildId, ChannelBinding, HashMap<Uuid, UserId>)> = states
+        .lock()
+        .await
+        .iter()
+        .filter_map(|(guild_id, state)| {
+            let requesters = state
+                .records
+                .iter()
+                .map(|(uuid, record)| (*uuid, record.requester))
+                .collect();
+
+            state.binding.map(|b| (*guild_id, b, requesters))
+        })
+        .collect();
+
+    let mut client = db::connect().await?;
+    let tx = client.transaction().await.map_err(db::log_err)?;
+
+    tx.execute("DELETE FROM saved_queues", &[])
+        .await
+        .map_err(db::log_err)?;
+
+    let mut saved = 0;
+    for (guild_id, binding, requesters) in bindings {
+        let call_m = if let Some(call_m) = songbird.get(guild_id) {
+            call_m
+        } else {
+            continue;
+        };
+
+        let tracks = call_m.lock().await.queue().current_queue();
+        let tracks = snapshot(&tracks, &requesters).await;
+
+        if tracks.is_empty() {
+            continue;
+        }
+
+        let db_guild_id = guild_id.0 as i64;
+
+        tx.execute(
+            "INSERT INTO saved_queues (guild_id, voice_channel_id, text_channel_id) VALUES ($1, $2, $3)",
+            &[
+                &db_guild_id,
+                &(binding.voice_channel_id.0 as i64),
+                &(binding.text_channel_id.0 as i64),
+            ],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+        for (position, track) in tracks.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO saved_queue_tracks (guild_id, position, url, requester_id, offset_ms) VALUES ($1, $2, $3, $4, $5)",
+                &[
+                    &db_guild_id,
+                    &(position as i32),
+                    &track.url,
+                    &track.requester.map(|u| u.0 as i64),
+                    &(track.offset.as_millis() as i64),
+                ],
+            )
+            .await
+            .map_err(db::log_err)?;
+        }
+
+        saved += 1;
+    }
+
+    tx.commit().await.map_err(db::log_err)?;
+
+    Ok(saved)
+}
+
+/// Rejoins a saved voice channel and enqueues the saved tracks, seeking the
+/// first one back to where it stopped.
+#[instrument(skip(ctx, tracks))]
+async fn restore_queue(
+    ctx: &Context,
+    guild_id: GuildId,
+    binding: ChannelBinding,
+    tracks: Vec<SavedTrack>,
+) -> SunnyResult<()> {
+    let call_m = join(&EventConfig {
+        ctx: ctx.clone(),
+        guild_id,
+        text_channel_id: binding.text_channel_id,
+        voice_channel_id: binding.voice_channel_id,
+    })
+    .await?;
+
+    deafen(call_m.clone()).await;
+
+    let bot_id = ctx.cache.current_user_id().await;
+
+    let mut restored = 0;
+    for track in tracks {
+        let record = TrackRecord::new(track.requester.unwrap_or(bot_id), "restore");
+
+        if let Err(e) = queue::play(ctx, guild_id, track.url, EnqueueAt::Back, record).await {
+            event!(Level::WARN, %e, "failed to restore track");
+            continue;
+        }
+
+        if restored == 0 && track.offset > Duration::default() {
+            let current = call_m.lock().await.queue().current();
+            if let Some(current) = current {
+                let position = position_at(&current, track.offset).await;
+                emit!(current.seek_time(position), Level::WARN);
+            }
+        }
+
+        restored += 1;
+    }
+
+    binding
+        .text_channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "I'm back! Restored {} songs from before my nap :sleeping:",
+                restored
+            ),
+        )
+        .await
+        .map_err(|e| SunnyError::log(format!("Failed to announce restore: {}", e).as_str()))?;
+
+    Ok(())
+}
+
+/// Restores every queue saved by [`save_queues`].
+#[instrument(skip(ctx))]
+pub async fn restore_queues(ctx: &Context) -> SunnyResult<()> {
+    let client = db::connect().await?;
+
+    let queues = client
+        .query(
+            "SELECT guild_id, voice_channel_id, text_channel_id FROM saved_queues",
+            &[],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    for row in queues {
+        let db_guild_id: i64 = row.get("guild_id");
+
+        let binding = ChannelBinding {
+            voice_channel_id: ChannelId(row.get::<_, i64>("voice_channel_id") as u64),
+            text_channel_id: ChannelId(row.get::<_, i64>("text_channel_id") as u64),
+        };
+
+        let tracks = client
+            .query(
+                "SELECT url, requester_id, offset_ms FROM saved_queue_tracks WHERE guild_id = $1 ORDER BY position",
+                &[&db_guild_id],
+            )
+            .await
+            .map_err(db::log_err)?
+            .into_iter()
+            .map(|row| SavedTrack {
+                url: row.get("url"),
+                requester: row
+                    .get::<_, Option<i64>>("requester_id")
+                    .map(|id| UserId(id as u64)),
+                offset: Duration::from_millis(row.get::<_, i64>("offset_ms") as u64),
+            })
+            .collect();
+
+        // Remove the saved queue first, a queue that fails to restore shouldn't
+        // be retried on every reconnect.
+        client
+            .execute(
+                "DELETE FROM saved_queues WHERE guild_id = $1",
+                &[&db_guild_id],
+            )
+            .await
+            .map_err(db::log_err)?;
+
+        let res = restore_queue(ctx, GuildId(db_guild_id as u64), binding, tracks).await;
+
+        emit!(res, Level::WARN);
+    }
+
+    Ok(())
+}
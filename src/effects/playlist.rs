@@ -0,0 +1,356 @@
+use std::time::Duration;
+
+use serenity::{
+    builder::CreateEmbed,
+    client::Context,
+    model::id::{GuildId, UserId},
+};
+use songbird::input::{Input, Restartable};
+use tokio_postgres::GenericClient;
+use tracing::{event, instrument, Level};
+
+use crate::{
+    db,
+    structs::TrackRecord,
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{
+    get_song,
+    queue::{self, EnqueueAt},
+    source_error::source_error,
+    split_duration,
+};
+
+/// Number of tracks `show` lists before cutting off
+const SHOWN_TRACKS: usize = 20;
+
+/// A track saved in a playlist
+#[derive(Clone, Debug)]
+pub struct PlaylistTrack {
+    pub url: String,
+    pub title: String,
+    pub duration: Option<Duration>,
+}
+
+impl From<tokio_postgres::Row> for PlaylistTrack {
+    fn from(row: tokio_postgres::Row) -> Self {
+        Self {
+            url: row.get("url"),
+            title: row.get("title"),
+            duration: row
+                .get::<_, Option<i64>>("duration_ms")
+                .map(|ms| Duration::from_millis(ms as u64)),
+        }
+    }
+}
+
+/// A playlist as shown by `playlist list`
+#[derive(Clone, Debug)]
+pub struct PlaylistSummary {
+    pub name: String,
+    pub owner: Option<UserId>,
+    pub tracks: i64,
+}
+
+fn db_owner(owner: Option<UserId>) -> Option<i64> {
+    owner.map(|o| o.0 as i64)
+}
+
+/// Names a playlist for replies, `owner` is `None` for the guild's playlists
+pub fn describe(name: &str, owner: Option<UserId>) -> String {
+    match owner {
+        Some(o) => format!("<@{}>'s playlist `{}`", o, name),
+        None => format!("playlist `{}`", name),
+    }
+}
+
+/// Finds the id of a playlist, names are case insensitive
+async fn find<C: GenericClient>(
+    client: &C,
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    name: &str,
+) -> SunnyResult<Option<i32>> {
+    Ok(client
+        .query_opt(
+            "SELECT id FROM playlists WHERE guild_id = $1 AND owner_id IS NOT DISTINCT FROM $2 AND lower(name) = lower($3)",
+            &[&(guild_id.0 as i64), &db_owner(owner), &name],
+        )
+        .await
+        .map_err(db::log_err)?
+        .map(|row| row.get("id")))
+}
+
+/// Finds a playlist, or errors with a message for the user
+async fn find_existing<C: GenericClient>(
+    client: &C,
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    name: &str,
+) -> SunnyResult<i32> {
+    find(client, guild_id, owner, name)
+        .await?
+        .ok_or_else(|| SunnyError::user(format!("There's no {}", describe(name, owner)).as_str()))
+}
+
+/// Finds a playlist, creating it if it doesn't exist yet
+async fn find_or_create<C: GenericClient>(
+    client: &C,
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    name: &str,
+) -> SunnyResult<i32> {
+    if let Some(id) = find(client, guild_id, owner, name).await? {
+        return Ok(id);
+    }
+
+    Ok(client
+        .query_one(
+            "INSERT INTO playlists (guild_id, owner_id, name) VALUES ($1, $2, $3) RETURNING id",
+            &[&(guild_id.0 as i64), &db_owner(owner), &name],
+        )
+        .await
+        .map_err(db::log_err)?
+        .get("id"))
+}
+
+async fn insert_track<C: GenericClient>(
+    client: &C,
+    playlist_id: i32,
+    track: &PlaylistTrack,
+) -> SunnyResult<()> {
+    client
+        .execute(
+            "INSERT INTO playlist_tracks (playlist_id, position, url, title, duration_ms) VALUES ($1, (SELECT coalesce(max(position) + 1, 0) FROM playlist_tracks WHERE playlist_id = $1), $2, $3, $4)",
+            &[
+                &playlist_id,
+                &track.url,
+                &track.title,
+                &track.duration.map(|d| d.as_millis() as i64),
+            ],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    Ok(())
+}
+
+/// Gets the tracks of a playlist in order
+#[instrument]
+pub async fn tracks(
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    name: &str,
+) -> SunnyResult<Vec<PlaylistTrack>> {
+    let client = db::connect().await?;
+    let id = find_existing(&client, guild_id, owner, name).await?;
+
+    Ok(client
+        .query(
+            "SELECT url, title, duration_ms FROM playlist_tracks WHERE playlist_id = $1 ORDER BY position",
+            &[&id],
+        )
+        .await
+        .map_err(db::log_err)?
+        .into_iter()
+        .map(PlaylistTrack::from)
+        .collect())
+}
+
+/// Saves the current queue as a playlist, replacing it if it already exists.
+///
+/// Returns the number of saved tracks.
+#[instrument(skip(ctx))]
+pub async fn save(
+    ctx: &Context,
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    name: &str,
+) -> SunnyResult<usize> {
+    let queue = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?
+        .lock()
+        .await
+        .queue()
+        .current_queue();
+
+    let tracks: Vec<PlaylistTrack> = queue
+        .iter()
+        .filter_map(|t| {
+            let m = t.metadata();
+
+            Some(PlaylistTrack {
+                url: m.source_url.clone()?,
+                title: get_song(m),
+                duration: m.duration,
+            })
+        })
+        .collect();
+
+    if tracks.is_empty() {
+        return Err(SunnyError::user("Nothing in the queue to save"));
+    }
+
+    let mut client = db::connect().await?;
+    let tx = client.transaction().await.map_err(db::log_err)?;
+    let id = find_or_create(&tx, guild_id, owner, name).await?;
+
+    tx.execute("DELETE FROM playlist_tracks WHERE playlist_id = $1", &[&id])
+        .await
+        .map_err(db::log_err)?;
+
+    for track in &tracks {
+        insert_track(&tx, id, track).await?;
+    }
+
+    tx.commit().await.map_err(db::log_err)?;
+
+    Ok(tracks.len())
+}
+
+/// Enqueues every track of a playlist, tracks that fail to load are skipped.
+///
+/// Returns the number of enqueued tracks.
+#[instrument(skip(ctx))]
+pub async fn load(
+    ctx: &Context,
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    name: &str,
+    requester: UserId,
+) -> SunnyResult<usize> {
+    let tracks = tracks(guild_id, owner, name).await?;
+
+    let mut loaded = 0;
+    for track in tracks {
+        let record = TrackRecord::new(requester, "playlist");
+
+        if let Err(e) = queue::play(ctx, guild_id, track.url, EnqueueAt::Back, record).await {
+            event!(Level::WARN, %e, "failed to load playlist track");
+            continue;
+        }
+
+        loaded += 1;
+    }
+
+    Ok(loaded)
+}
+
+/// Adds a url to the end of a playlist, creating the playlist if needed.
+///
+/// Returns the title of the added track.
+#[instrument]
+pub async fn add(
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    name: &str,
+    url: String,
+) -> SunnyResult<String> {
+    let source: Input = Restartable::ytdl(url.clone(), true)
+        .await
+        .map_err(|e| source_error(&url, &e))?
+        .into();
+
+    let track = PlaylistTrack {
+        url,
+        title: get_song(&source.metadata),
+        duration: source.metadata.duration,
+    };
+
+    let client = db::connect().await?;
+    let id = find_or_create(&client, guild_id, owner, name).await?;
+    insert_track(&client, id, &track).await?;
+
+    Ok(track.title)
+}
+
+/// Removes the track at `position` (starting at 1) from a playlist, returning
+/// its title
+#[instrument]
+pub async fn remove(
+    guild_id: GuildId,
+    owner: Option<UserId>,
+    name: &str,
+    position: usize,
+) -> SunnyResult<String> {
+    if position == 0 {
+        return Err(SunnyError::user(
+            "A song index of 0 is invalid (The playlist starts at 1)",
+        ));
+    }
+
+    let client = db::connect().await?;
+    let id = find_existing(&client, guild_id, owner, name).await?;
+
+    client
+        .query_opt(
+            "DELETE FROM playlist_tracks WHERE id = (SELECT id FROM playlist_tracks WHERE playlist_id = $1 ORDER BY position OFFSET $2 LIMIT 1) RETURNING title",
+            &[&id, &(position as i64 - 1)],
+        )
+        .await
+        .map_err(db::log_err)?
+        .map(|row| row.get("title"))
+        .ok_or_else(|| {
+            SunnyError::user(format!("There's no song at position {}", position).as_str())
+        })
+}
+
+/// Lists the guild's playlists and the playlists of `user` in it
+#[instrument]
+pub async fn list(guild_id: GuildId, user: UserId) -> SunnyResult<Vec<PlaylistSummary>> {
+    let rows = db::connect()
+        .await?
+        .query(
+            "SELECT p.name, p.owner_id, count(t.id) AS tracks FROM playlists p LEFT JOIN playlist_tracks t ON t.playlist_id = p.id WHERE p.guild_id = $1 AND (p.owner_id IS NULL OR p.owner_id = $2) GROUP BY p.id ORDER BY p.owner_id NULLS FIRST, lower(p.name)",
+            &[&(guild_id.0 as i64), &(user.0 as i64)],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| PlaylistSummary {
+            name: row.get("name"),
+            owner: row
+                .get::<_, Option<i64>>("owner_id")
+                .map(|id| UserId(id as u64)),
+            tracks: row.get("tracks"),
+        })
+        .collect())
+}
+
+/// Generates an embed listing the tracks of a playlist
+pub fn generate_embed(name: &str, owner: Option<UserId>, tracks: &[PlaylistTrack]) -> CreateEmbed {
+    let mut e = CreateEmbed::default();
+    e.author(|a| a.name(format!("Playlist: {}", name)));
+
+    let mut lines: Vec<String> = tracks
+        .iter()
+        .take(SHOWN_TRACKS)
+        .enumerate()
+        .map(|(i, t)| format!("**{}.** [{}]({})", i + 1, t.title, t.url))
+        .collect();
+
+    if tracks.len() > SHOWN_TRACKS {
+        lines.push(format!("...and {} more", tracks.len() - SHOWN_TRACKS));
+    }
+
+    if lines.is_empty() {
+        e.description("This playlist is empty");
+    } else {
+        e.description(lines.join("\n"));
+    }
+
+    if let Some(owner) = owner {
+        e.field("Owner", format!("<@{}>", owner), true);
+    }
+
+    let (min, sec) = split_duration(tracks.iter().filter_map(|t| t.duration).sum());
+    e.footer(|f| f.text(format!("{} songs | {}:{:02}", tracks.len(), min, sec)));
+
+    e
+}
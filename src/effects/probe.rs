@@ -0,0 +1,129 @@
+use std::{path::Path, process::Stdio, time::Duration};
+
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+use tokio::process::Command;
+use tracing::{event, instrument, Level};
+use url::Url;
+
+use crate::utils::{SunnyError, SunnyResult};
+
+/// File extensions of audio files, for the library and direct urls
+pub const AUDIO_EXTENSIONS: &[&str] = &["flac", "mp3", "ogg", "opus", "wav", "m4a", "aac"];
+
+/// Cover art bigger than this isn't worth attaching
+const MAX_COVER_SIZE: usize = 8 * 1024 * 1024;
+
+/// What ffprobe found out about a file or direct url
+#[derive(Clone, Debug, Default)]
+pub struct Probe {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+/// Looks up a tag, formats disagree on the case of tag names (FLAC files
+/// tend to use `TITLE`, MP3 files `title`)
+fn tag(tags: Option<&Value>, name: &str) -> Option<String> {
+    tags?
+        .as_object()?
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .and_then(|(_, v)| v.as_str())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+}
+
+/// Reads the tags and duration of a file or direct url with ffprobe
+#[instrument]
+pub async fn probe(input: &str) -> SunnyResult<Probe> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "quiet", "-of", "json", "-show_format", input])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| SunnyError::log(format!("Failed to run ffprobe: {}", e).as_str()))?;
+
+    if !output.status.success() {
+        return Err(SunnyError::log(
+            format!("ffprobe failed on {}: {}", input, output.status).as_str(),
+        ));
+    }
+
+    let json: Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| SunnyError::log(format!("Invalid ffprobe output: {}", e).as_str()))?;
+
+    let format = json.get("format");
+    let tags = format.and_then(|f| f.get("tags"));
+
+    Ok(Probe {
+        title: tag(tags, "title"),
+        artist: tag(tags, "artist").or_else(|| tag(tags, "album_artist")),
+        album: tag(tags, "album"),
+        duration: format
+            .and_then(|f| f.get("duration"))
+            .and_then(Value::as_str)
+            .and_then(|d| d.parse::<f64>().ok())
+            .filter(|d| d.is_finite() && *d >= 0.0)
+            .map(Duration::from_secs_f64),
+    })
+}
+
+/// Extracts the embedded cover art of a file or direct url as a JPEG
+#[instrument]
+pub async fn cover_art(input: &str) -> Option<Vec<u8>> {
+    let output = Command::new("ffmpeg")
+        .args([
+            "-v",
+            "quiet",
+            "-i",
+            input,
+            "-map",
+            "0:v:0",
+            "-frames:v",
+            "1",
+            "-f",
+            "mjpeg",
+            "-",
+        ])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| event!(Level::WARN, %e, "failed to run ffmpeg for cover art"))
+        .ok()?;
+
+    (output.status.success() && !output.stdout.is_empty() && output.stdout.len() <= MAX_COVER_SIZE)
+        .then_some(output.stdout)
+}
+
+/// Whether `path` ends in the extension of an audio file
+pub fn is_audio_file(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+
+    matches!(extension, Some(e) if AUDIO_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// Parses `url` if it points straight at an audio file instead of a page
+/// ytdl has to extract it from
+pub fn direct_url(url: &str) -> Option<Url> {
+    let parsed = Url::parse(url).ok()?;
+
+    (matches!(parsed.scheme(), "http" | "https") && is_audio_file(Path::new(parsed.path())))
+        .then_some(parsed)
+}
+
+/// The decoded file name of a url, without its extension
+pub fn url_file_title(url: &Url) -> Option<String> {
+    let file = url.path_segments()?.next_back()?;
+    let file = percent_decode_str(file).decode_utf8_lossy();
+
+    Path::new(file.as_ref())
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .filter(|s| !s.is_empty())
+}
@@ -0,0 +1,168 @@
+use std::collections::{HashMap, VecDeque};
+
+use serenity::{client::Context, model::id::GuildId, model::id::UserId};
+use songbird::tracks::{Queued, TrackHandle};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    structs::with_guild_state,
+    utils::{SunnyError, SunnyResult},
+};
+
+/// Something in the queue, known by the uuid of its track
+pub trait QueueItem {
+    fn uuid(&self) -> Uuid;
+}
+
+impl QueueItem for Queued {
+    fn uuid(&self) -> Uuid {
+        TrackHandle::uuid(self)
+    }
+}
+
+/// Gives every upcoming track its round: the number of earlier upcoming tracks
+/// by the same requester. Element 0 is the current track and has no round.
+fn rounds<T: QueueItem>(q: &VecDeque<T>, requesters: &HashMap<Uuid, UserId>) -> Vec<usize> {
+    let mut seen: HashMap<Option<UserId>, usize> = HashMap::new();
+
+    q.iter()
+        .skip(1)
+        .map(|t| {
+            let count = seen.entry(requesters.get(&t.uuid()).copied()).or_default();
+            *count += 1;
+            *count - 1
+        })
+        .collect()
+}
+
+/// Finds the index a new track by `requester` goes to, so requesters take
+/// turns: it is placed after every track in its round.
+pub fn fair_index<T: QueueItem>(
+    q: &VecDeque<T>,
+    requesters: &HashMap<Uuid, UserId>,
+    requester: UserId,
+) -> usize {
+    let round = q
+        .iter()
+        .skip(1)
+        .filter(|t| requesters.get(&t.uuid()) == Some(&requester))
+        .count();
+
+    rounds(q, requesters)
+        .into_iter()
+        .position(|r| r > round)
+        .map_or(q.len(), |i| i + 1)
+}
+
+/// Stable sorts the upcoming tracks (element 0 stays put) into round-robin
+/// order across requesters.
+pub fn fair_sort<T: QueueItem>(q: &mut VecDeque<T>, requesters: &HashMap<Uuid, UserId>) {
+    if q.len() < 3 {
+        return;
+    }
+
+    let rounds = rounds(q, requesters);
+    let current = q.pop_front();
+
+    let mut upcoming: Vec<(usize, T)> = rounds.into_iter().zip(q.drain(..)).collect();
+    upcoming.sort_by_key(|(round, _)| *round);
+
+    q.extend(upcoming.into_iter().map(|(_, t)| t));
+    if let Some(current) = current {
+        q.push_front(current);
+    }
+}
+
+/// Snapshots the requester of every track Sunny has a record of
+pub async fn requesters(ctx: &Context, guild_id: GuildId) -> SunnyResult<HashMap<Uuid, UserId>> {
+    with_guild_state(ctx, guild_id, |s| {
+        s.records
+            .iter()
+            .map(|(uuid, r)| (*uuid, r.requester))
+            .collect()
+    })
+    .await
+}
+
+/// Turns fair queue mode on or off, turning it on rebalances the queue
+#[instrument(skip(ctx))]
+pub async fn set_fair(ctx: &Context, guild_id: GuildId, enabled: bool) -> SunnyResult<()> {
+    with_guild_state(ctx, guild_id, |s| s.fair_queue = enabled).await?;
+
+    if !enabled {
+        return Ok(());
+    }
+
+    let requesters = requesters(ctx, guild_id).await?;
+
+    songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?
+        .lock()
+        .await
+        .queue()
+        .modify_queue(|q| fair_sort(q, &requesters));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::queue::test_queue::{ids, queue};
+
+    const ALICE: UserId = UserId(1);
+    const BOB: UserId = UserId(2);
+
+    /// A queue of tracks by the given requesters, the current one by nobody
+    fn queue_by(by: &[UserId]) -> (VecDeque<Uuid>, HashMap<Uuid, UserId>) {
+        let q = queue(by.len() + 1);
+        let requesters = q.iter().skip(1).copied().zip(by.iter().copied()).collect();
+
+        (q, requesters)
+    }
+
+    #[test]
+    fn new_requester_goes_after_first_round() {
+        let (q, requesters) = queue_by(&[ALICE, ALICE, ALICE]);
+
+        assert_eq!(fair_index(&q, &requesters, BOB), 2);
+    }
+
+    #[test]
+    fn requester_goes_after_their_round() {
+        let (q, requesters) = queue_by(&[ALICE, BOB, ALICE, ALICE]);
+
+        assert_eq!(fair_index(&q, &requesters, BOB), 4);
+        assert_eq!(fair_index(&q, &requesters, ALICE), q.len());
+    }
+
+    #[test]
+    fn fair_index_of_empty_queue() {
+        let (q, requesters) = queue_by(&[]);
+
+        assert_eq!(fair_index(&q, &requesters, ALICE), 1);
+        assert_eq!(fair_index(&VecDeque::<Uuid>::new(), &requesters, ALICE), 0);
+    }
+
+    #[test]
+    fn sort_takes_turns_and_keeps_current() {
+        let (mut q, requesters) = queue_by(&[ALICE, ALICE, ALICE, BOB, BOB]);
+
+        fair_sort(&mut q, &requesters);
+
+        assert_eq!(ids(&q), vec![0, 1, 4, 2, 5, 3]);
+    }
+
+    #[test]
+    fn sort_keeps_order_within_a_round() {
+        let (mut q, requesters) = queue_by(&[BOB, ALICE, BOB, ALICE]);
+
+        fair_sort(&mut q, &requesters);
+
+        assert_eq!(ids(&q), vec![0, 1, 2, 3, 4]);
+    }
+}
@@ -1,8 +1,11 @@
 //! # Queue Effects
 //! These effects affect the queue in some way or another.
 
+mod fair;
+mod move_to;
 mod pause;
 mod play;
+mod remove;
 mod remove_at;
 mod resume;
 mod shuffle;
@@ -10,11 +13,41 @@ mod skip;
 mod stop;
 mod swap;
 
+pub use fair::set_fair;
+pub use move_to::move_to;
 pub use pause::pause;
 pub use play::{play, EnqueueAt};
+pub use remove::{clear_upcoming, clear_upcoming_but, dedupe, remove_by, remove_range};
 pub use remove_at::remove_at;
 pub use resume::resume;
-pub use shuffle::shuffle;
+pub use shuffle::{set_continuous, shuffle, unshuffle};
 pub use skip::skip;
 pub use stop::stop;
 pub use swap::swap;
+
+#[cfg(test)]
+mod test_queue {
+    //! Queues of bare uuids, to test the queue logic without tracks
+
+    use std::collections::VecDeque;
+
+    use uuid::Uuid;
+
+    use super::fair::QueueItem;
+
+    impl QueueItem for Uuid {
+        fn uuid(&self) -> Uuid {
+            *self
+        }
+    }
+
+    /// A queue of `len` uuids counting up from 0, the current track included
+    pub fn queue(len: usize) -> VecDeque<Uuid> {
+        (0..len as u128).map(Uuid::from_u128).collect()
+    }
+
+    /// The numbers of the uuids in `q`
+    pub fn ids(q: &VecDeque<Uuid>) -> Vec<u128> {
+        q.iter().map(|u| u.as_u128()).collect()
+    }
+}
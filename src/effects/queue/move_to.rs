@@ -0,0 +1,40 @@
+use serenity::{client::Context, model::id::GuildId};
+use songbird::tracks::TrackHandle;
+use tracing::instrument;
+
+use crate::utils::{SunnyError, SunnyResult};
+
+use super::swap::validate_index;
+
+/// Moves the song at `from` to `to`, shifting the songs in between so
+/// everything else keeps its order.
+#[instrument(skip(ctx))]
+pub async fn move_to(
+    ctx: &Context,
+    guild_id: GuildId,
+    from: usize,
+    to: usize,
+) -> SunnyResult<TrackHandle> {
+    let call_m = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?;
+
+    let call = call_m.lock().await;
+
+    let q = call.queue();
+
+    validate_index(from, q.len())?;
+    validate_index(to, q.len())?;
+
+    q.modify_queue(|q| {
+        let track = q
+            .remove(from)
+            .ok_or_else(|| SunnyError::log("Validated index out of bounds"))?;
+        let handle = track.handle();
+        q.insert(to, track);
+
+        Ok(handle)
+    })
+}
@@ -1,29 +1,41 @@
+use std::{collections::HashMap, sync::Arc};
+
 use serenity::{client::Context, model::id::GuildId};
-use songbird::input::Restartable;
+use songbird::{create_player, Event};
 use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    effects::{fade, source::create_source},
+    handlers::CrossfadeHandler,
+    structs::{with_guild_state, CoverArt, TrackClock, TrackRecord},
+    utils::{SunnyError, SunnyResult},
+};
 
-use crate::utils::{SunnyError, SunnyResult};
+use super::{
+    fair::{fair_index, fair_sort, requesters},
+    shuffle::random_index,
+    swap::validate_index,
+};
 
 #[derive(Debug)]
 pub enum EnqueueAt {
     Front,
     Back,
+    /// 1-based position in the queue, the song after the current one is 1
+    Index(usize),
 }
 
+/// Enqueues the given url, returning the position of the new track in the
+/// queue (0 means it started playing)
 #[instrument(skip(ctx))]
 pub async fn play(
     ctx: &Context,
     guild_id: GuildId,
     url: String,
     enqueu_at: EnqueueAt,
+    record: TrackRecord,
 ) -> SunnyResult<usize> {
-    let source = Restartable::ytdl(url, true).await.map_err(|e| {
-        SunnyError::user_and_log(
-            "Error starting stream",
-            format!("Error sourcing ffmpeg {:?}", e).as_str(),
-        )
-    })?;
-
     let songbird = songbird::get(ctx)
         .await
         .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?;
@@ -32,21 +44,111 @@ pub async fn play(
         .get(guild_id)
         .ok_or_else(|| SunnyError::log("No Call"))?;
 
-    let mut call = call_m.lock().await;
+    if let EnqueueAt::Index(index) = enqueu_at {
+        // Inserting right after the last song is fine too
+        let len = call_m.lock().await.queue().len();
+        if len > 0 {
+            validate_index(index, len + 1)?;
+        }
+    }
+
+    let (audio, volume) =
+        with_guild_state(ctx, guild_id, |s| (s.audio.clone(), s.music_volume())).await?;
+    let (source, cover, clock) = create_source(&url, audio.clone()).await?;
+
+    let (mut track, handle) = create_player(source);
+    track.set_volume(volume);
+
+    let queue = call_m.lock().await.queue().clone();
+    handle
+        .add_event(
+            Event::Periodic(fade::CHECK_INTERVAL, None),
+            CrossfadeHandler {
+                ctx: ctx.clone(),
+                guild_id,
+                queue,
+                audio,
+            },
+        )
+        .map_err(|e| SunnyError::log(format!("Failed to add crossfade handler: {}", e).as_str()))?;
+
+    {
+        let mut typemap = handle.typemap().write().await;
+        typemap.insert::<TrackClock>(clock);
+
+        if let Some(cover) = cover {
+            typemap.insert::<CoverArt>(Arc::new(cover));
+        }
+    }
+    let requester = record.requester;
+    let by_user = !record.is_autoplay();
+
+    let (fair, shuffled, autoplayed) = with_guild_state(ctx, guild_id, |s| {
+        s.records.insert(handle.uuid(), record);
 
-    match enqueu_at {
-        EnqueueAt::Front => {
-            call.enqueue_source(source.into());
-            call.queue().modify_queue(|q| {
-                if let Some(track) = q.pop_back() {
-                    q.push_front(track);
-                    if q.len() > 1 {
-                        q.swap(0, 1);
-                    }
-                }
-            });
+        // Unshuffling puts songs added to the back after the others
+        if let (EnqueueAt::Back, Some(order)) = (&enqueu_at, &mut s.unshuffled) {
+            order.push(handle.uuid());
         }
-        EnqueueAt::Back => call.enqueue_source(source.into()),
+
+        let autoplayed: Vec<Uuid> = s
+            .records
+            .iter()
+            .filter(|(_, r)| r.is_autoplay())
+            .map(|(uuid, _)| *uuid)
+            .collect();
+
+        (s.fair_queue, s.continuous_shuffle, autoplayed)
+    })
+    .await?;
+
+    let requesters = if fair {
+        requesters(ctx, guild_id).await?
+    } else {
+        HashMap::new()
     };
-    Ok(call.queue().len())
+
+    let mut call = call_m.lock().await;
+
+    call.enqueue(track);
+    call.queue().modify_queue(|q| {
+        let track = if let Some(track) = q.pop_back() {
+            track
+        } else {
+            return;
+        };
+
+        let index = match enqueu_at {
+            EnqueueAt::Front => 1,
+            EnqueueAt::Index(index) => index,
+            EnqueueAt::Back if shuffled => random_index(q.len()),
+            EnqueueAt::Back if fair => fair_index(q, &requesters, requester),
+            EnqueueAt::Back => q.len(),
+        };
+
+        q.insert(index.min(q.len()), track);
+
+        // A random position can cut in line, fair queue puts it back in its round
+        if shuffled && fair && matches!(enqueu_at, EnqueueAt::Back) {
+            fair_sort(q, &requesters);
+        }
+    });
+
+    let uuid = handle.uuid();
+    let position = call
+        .queue()
+        .modify_queue(|q| q.iter().position(|t| t.uuid() == uuid))
+        .unwrap_or_default();
+
+    // Autoplay only fills silence, songs queued by users take over right away
+    let current = call.queue().current().map(|t| t.uuid());
+    if by_user && position > 0 && matches!(current, Some(c) if autoplayed.contains(&c)) {
+        call.queue().skip().map_err(|e| {
+            SunnyError::log(format!("Failed to skip autoplayed song: {}", e).as_str())
+        })?;
+
+        return Ok(position - 1);
+    }
+
+    Ok(position)
 }
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+
+use serenity::{
+    client::Context,
+    model::id::{GuildId, UserId},
+};
+use songbird::tracks::Queued;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    checks::is_dj,
+    structs::with_guild_state,
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{fair::requesters, swap::validate_index};
+
+/// Removes every upcoming song matching `pred`, the current song is never
+/// removed. Returns the number of removed songs.
+async fn remove_where<F>(ctx: &Context, guild_id: GuildId, mut pred: F) -> SunnyResult<usize>
+where
+    F: FnMut(&Queued) -> bool,
+{
+    let call_m = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?;
+
+    let call = call_m.lock().await;
+
+    let removed: Vec<Queued> = call.queue().modify_queue(|q| {
+        let mut kept = Vec::with_capacity(q.len());
+        let mut removed = Vec::new();
+
+        for (i, track) in q.drain(..).enumerate() {
+            // pred sees the current track too, it just never gets removed
+            let matches = pred(&track);
+
+            if i > 0 && matches {
+                removed.push(track);
+            } else {
+                kept.push(track);
+            }
+        }
+
+        q.extend(kept);
+        removed
+    });
+
+    let uuids: HashSet<Uuid> = removed.iter().map(|t| t.uuid()).collect();
+    with_guild_state(ctx, guild_id, |s| {
+        s.records.retain(|uuid, _| !uuids.contains(uuid));
+        s.history_ids.retain(|uuid, _| !uuids.contains(uuid));
+        s.retried.retain(|uuid| !uuids.contains(uuid));
+
+        if let Some(order) = &mut s.unshuffled {
+            order.retain(|uuid| !uuids.contains(uuid));
+        }
+    })
+    .await?;
+
+    // Removed tracks have to be stopped to free their resources, their state
+    // is gone so ending has nothing left to clean up
+    for track in &removed {
+        track.stop().ok();
+    }
+
+    Ok(removed.len())
+}
+
+/// Removes the songs from position `from` up to and including `to`. Anyone but
+/// a DJ can only remove a range of their own songs.
+#[instrument(skip(ctx))]
+pub async fn remove_range(
+    ctx: &Context,
+    guild_id: GuildId,
+    from: usize,
+    to: usize,
+    user_id: UserId,
+) -> SunnyResult<usize> {
+    let (from, to) = (from.min(to), from.max(to));
+
+    let uuids = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?
+        .lock()
+        .await
+        .queue()
+        .modify_queue(|q| {
+            validate_index(from, q.len())?;
+            validate_index(to, q.len())?;
+
+            Ok::<HashSet<_>, SunnyError>(
+                q.iter()
+                    .skip(from)
+                    .take(to - from + 1)
+                    .map(|t| t.uuid())
+                    .collect(),
+            )
+        })?;
+
+    let requesters = requesters(ctx, guild_id).await?;
+    let all_own = uuids
+        .iter()
+        .all(|uuid| requesters.get(uuid) == Some(&user_id));
+
+    if !all_own && !is_dj(ctx, guild_id, user_id).await? {
+        return Err(SunnyError::user(
+            "Only DJs can remove a range with other people's songs",
+        ));
+    }
+
+    remove_where(ctx, guild_id, |t| uuids.contains(&t.uuid())).await
+}
+
+/// Removes every upcoming song requested by `requester`
+#[instrument(skip(ctx))]
+pub async fn remove_by(ctx: &Context, guild_id: GuildId, requester: UserId) -> SunnyResult<usize> {
+    let requesters = requesters(ctx, guild_id).await?;
+
+    remove_where(ctx, guild_id, |t| {
+        requesters.get(&t.uuid()) == Some(&requester)
+    })
+    .await
+}
+
+/// Removes upcoming songs with a source url that is already in the queue.
+/// Anyone but a DJ only removes their own duplicates.
+#[instrument(skip(ctx))]
+pub async fn dedupe(ctx: &Context, guild_id: GuildId, user_id: UserId) -> SunnyResult<usize> {
+    let requesters = requesters(ctx, guild_id).await?;
+    let dj = is_dj(ctx, guild_id, user_id).await?;
+    let mut seen = HashSet::new();
+
+    remove_where(ctx, guild_id, |t| {
+        let duplicate = match &t.metadata().source_url {
+            Some(url) => !seen.insert(url.clone()),
+            None => false,
+        };
+
+        duplicate && (dj || requesters.get(&t.uuid()) == Some(&user_id))
+    })
+    .await
+}
+
+/// Removes every upcoming song, but keeps the current one playing
+#[instrument(skip(ctx))]
+pub async fn clear_upcoming(ctx: &Context, guild_id: GuildId) -> SunnyResult<usize> {
+    remove_where(ctx, guild_id, |_| true).await
+}
+
+/// Removes every upcoming song but the one with uuid `keep`
+#[instrument(skip(ctx))]
+pub async fn clear_upcoming_but(
+    ctx: &Context,
+    guild_id: GuildId,
+    keep: Uuid,
+) -> SunnyResult<usize> {
+    remove_where(ctx, guild_id, |t| t.uuid() != keep).await
+}
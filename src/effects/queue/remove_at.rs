@@ -1,21 +1,56 @@
 use std::num::NonZeroUsize;
 
-use serenity::{client::Context, model::id::GuildId};
+use serenity::{
+    client::Context,
+    model::id::{GuildId, UserId},
+};
 use songbird::tracks::Queued;
 use tracing::instrument;
 
-use crate::utils::{SunnyError, SunnyResult};
+use crate::{
+    checks::can_manage_track,
+    utils::{SunnyError, SunnyResult},
+};
 
 #[instrument(skip(ctx))]
-pub async fn remove_at(ctx: &Context, guild_id: GuildId, at: NonZeroUsize) -> SunnyResult<Queued> {
-    songbird::get(ctx)
+pub async fn remove_at(
+    ctx: &Context,
+    guild_id: GuildId,
+    at: NonZeroUsize,
+    user_id: UserId,
+) -> SunnyResult<Queued> {
+    let call_m = songbird::get(ctx)
         .await
         .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
         .get(guild_id)
-        .ok_or_else(|| SunnyError::log("No Call"))?
+        .ok_or_else(|| SunnyError::log("No Call"))?;
+
+    let uuid = call_m
+        .lock()
+        .await
+        .queue()
+        .modify_queue(|q| q.get(at.get()).map(|t| t.uuid()))
+        .ok_or_else(|| SunnyError::user("Nothing to remove..."))?;
+
+    if !can_manage_track(ctx, guild_id, user_id, uuid).await? {
+        return Err(SunnyError::user(
+            "Only the requester or a DJ can remove that song",
+        ));
+    }
+
+    let removed = call_m
         .lock()
         .await
         .queue()
-        .dequeue(at.into())
-        .ok_or_else(|| SunnyError::user("Nothing to remove..."))
+        .modify_queue(|q| {
+            // The queue may have moved on while checking permissions
+            let index = q.iter().position(|t| t.uuid() == uuid)?;
+            q.remove(index)
+        })
+        .ok_or_else(|| SunnyError::user("Nothing to remove..."))?;
+
+    // Removed tracks have to be stopped to free their resources
+    removed.stop().ok();
+
+    Ok(removed)
 }
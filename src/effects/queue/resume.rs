@@ -1,10 +1,18 @@
 use serenity::{client::Context, model::id::GuildId};
 use tracing::instrument;
 
-use crate::utils::{SunnyError, SunnyResult};
+use crate::{
+    effects::fade,
+    utils::{SunnyError, SunnyResult},
+};
 
 #[instrument(skip(ctx))]
 pub async fn resume(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
+    // A crossfade pauses and resumes as a whole
+    for track in fade::fading_out(ctx, guild_id).await? {
+        let _ = track.play();
+    }
+
     songbird::get(ctx)
         .await
         .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
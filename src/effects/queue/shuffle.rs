@@ -1,28 +1,45 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
-use rand::{rngs::SmallRng, SeedableRng};
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use serenity::{client::Context, model::id::GuildId};
+use songbird::tracks::TrackQueue;
 use tracing::instrument;
+use uuid::Uuid;
 
-use crate::utils::{SunnyError, SunnyResult};
+use crate::{
+    structs::with_guild_state,
+    utils::{SunnyError, SunnyResult},
+};
 
-/// Shuffles a `VecDeque` except element 0, why? implementation details
-fn shuffle_vdq<T, R>(values: &mut VecDeque<T>, mut rng: R)
-where
-    R: rand::Rng,
-{
-    let mut i = values.len();
-    while i >= 2 {
-        // invariant: elements with index >= i have been locked in place.
-        i -= 1;
-        // lock element i in place.
-        values.swap(i, rng.gen_range(1..=i));
+use super::fair::{fair_sort, requesters, QueueItem};
+
+/// Puts the upcoming tracks (element 0 stays put) back in `order`, tracks
+/// that aren't in it go after the others in their current order.
+fn restore_order<T: QueueItem>(q: &mut VecDeque<T>, order: &[Uuid]) {
+    let ranks: HashMap<Uuid, usize> = order.iter().enumerate().map(|(i, u)| (*u, i)).collect();
+
+    if let Some(upcoming) = q.make_contiguous().get_mut(1..) {
+        upcoming.sort_by_key(|t| ranks.get(&t.uuid()).copied().unwrap_or(usize::MAX));
     }
 }
 
-#[instrument(skip(ctx))]
-pub async fn shuffle(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
-    songbird::get(ctx)
+/// Shuffles the upcoming tracks (element 0 stays put) with `seed`. ChaCha
+/// gives the same order for a seed on every platform and rand version.
+fn shuffle_upcoming<T>(q: &mut VecDeque<T>, seed: u64) {
+    if let Some(upcoming) = q.make_contiguous().get_mut(1..) {
+        upcoming.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+    }
+}
+
+/// Picks a random upcoming position for a new track in continuous shuffle
+/// mode, given the length of the queue. The end of the queue is included.
+pub fn random_index(len: usize) -> usize {
+    SmallRng::from_entropy().gen_range(1..=len.max(1))
+}
+
+async fn track_queue(ctx: &Context, guild_id: GuildId) -> SunnyResult<TrackQueue> {
+    Ok(songbird::get(ctx)
         .await
         .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
         .get(guild_id)
@@ -30,10 +47,157 @@ pub async fn shuffle(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
         .lock()
         .await
         .queue()
-        .modify_queue(|q| {
-            let rng = SmallRng::from_entropy();
-            shuffle_vdq(q, rng);
-        });
+        .clone())
+}
+
+/// Shuffles the upcoming tracks, returning the seed so the same order can be
+/// shuffled again. A shuffled queue is shuffled from its order before the
+/// first shuffle, so a seed always gives the same order. In fair queue mode
+/// requesters keep taking turns.
+#[instrument(skip(ctx))]
+pub async fn shuffle(ctx: &Context, guild_id: GuildId, seed: Option<u64>) -> SunnyResult<u64> {
+    let seed = seed.unwrap_or_else(|| SmallRng::from_entropy().gen());
+
+    let (fair, unshuffled) =
+        with_guild_state(ctx, guild_id, |s| (s.fair_queue, s.unshuffled.clone())).await?;
+    let requesters = if fair {
+        Some(requesters(ctx, guild_id).await?)
+    } else {
+        None
+    };
+
+    let original = track_queue(ctx, guild_id).await?.modify_queue(|q| {
+        if let Some(order) = &unshuffled {
+            restore_order(q, order);
+        }
+        let original: Vec<Uuid> = q.iter().skip(1).map(|t| t.uuid()).collect();
+
+        shuffle_upcoming(q, seed);
+
+        if let Some(requesters) = &requesters {
+            fair_sort(q, requesters);
+        }
+
+        original
+    });
+
+    with_guild_state(ctx, guild_id, |s| s.unshuffled = Some(original)).await?;
+
+    Ok(seed)
+}
+
+/// Puts the upcoming tracks back in the order they had before `shuffle` and
+/// stops continuous shuffle. Tracks added since go after the others.
+#[instrument(skip(ctx))]
+pub async fn unshuffle(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
+    let (fair, unshuffled) = with_guild_state(ctx, guild_id, |s| {
+        s.continuous_shuffle = false;
+        (s.fair_queue, s.unshuffled.take())
+    })
+    .await?;
+
+    let order = unshuffled.ok_or_else(|| SunnyError::user("The queue isn't shuffled"))?;
+    let requesters = if fair {
+        Some(requesters(ctx, guild_id).await?)
+    } else {
+        None
+    };
+
+    track_queue(ctx, guild_id).await?.modify_queue(|q| {
+        restore_order(q, &order);
+
+        if let Some(requesters) = &requesters {
+            fair_sort(q, requesters);
+        }
+    });
 
     Ok(())
 }
+
+/// Turns continuous shuffle on or off, turning it on shuffles the queue.
+/// Returns the seed of that shuffle.
+#[instrument(skip(ctx))]
+pub async fn set_continuous(
+    ctx: &Context,
+    guild_id: GuildId,
+    enabled: bool,
+) -> SunnyResult<Option<u64>> {
+    with_guild_state(ctx, guild_id, |s| s.continuous_shuffle = enabled).await?;
+
+    if !enabled {
+        return Ok(None);
+    }
+
+    shuffle(ctx, guild_id, None).await.map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::queue::test_queue::{ids, queue};
+
+    #[test]
+    fn same_seed_same_order() {
+        let (mut a, mut b) = (queue(20), queue(20));
+
+        shuffle_upcoming(&mut a, 42);
+        shuffle_upcoming(&mut b, 42);
+
+        assert_eq!(a, b);
+        assert_ne!(a, queue(20));
+    }
+
+    #[test]
+    fn other_seed_other_order() {
+        let (mut a, mut b) = (queue(20), queue(20));
+
+        shuffle_upcoming(&mut a, 1);
+        shuffle_upcoming(&mut b, 2);
+
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn shuffle_keeps_current() {
+        let mut q = queue(20);
+
+        for seed in 0..50 {
+            shuffle_upcoming(&mut q, seed);
+            assert_eq!(q[0].as_u128(), 0);
+        }
+    }
+
+    #[test]
+    fn restore_puts_back_order() {
+        let order: Vec<Uuid> = queue(6).into_iter().skip(1).collect();
+        let mut q = queue(6);
+
+        shuffle_upcoming(&mut q, 7);
+        restore_order(&mut q, &order);
+
+        assert_eq!(q, queue(6));
+    }
+
+    #[test]
+    fn restore_puts_new_tracks_last() {
+        let order: Vec<Uuid> = [1, 2, 3].into_iter().map(Uuid::from_u128).collect();
+        let mut q: VecDeque<Uuid> = [0, 5, 3, 4, 1, 2]
+            .into_iter()
+            .map(Uuid::from_u128)
+            .collect();
+
+        restore_order(&mut q, &order);
+
+        assert_eq!(ids(&q), vec![0, 1, 2, 3, 5, 4]);
+    }
+
+    #[test]
+    fn random_index_skips_current() {
+        for len in 0..5 {
+            for _ in 0..100 {
+                let index = random_index(len);
+                assert!((1..=len.max(1)).contains(&index));
+            }
+        }
+    }
+}
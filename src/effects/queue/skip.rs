@@ -1,10 +1,15 @@
 use serenity::{client::Context, model::id::GuildId};
 use tracing::instrument;
 
-use crate::utils::{SunnyError, SunnyResult};
+use crate::{
+    effects::fade,
+    utils::{SunnyError, SunnyResult},
+};
 
 #[instrument(skip(ctx))]
 pub async fn skip(ctx: &Context, guild_id: GuildId) -> SunnyResult<usize> {
+    fade::fade_out_current(ctx, guild_id).await?;
+
     let call_m = songbird::get(ctx)
         .await
         .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
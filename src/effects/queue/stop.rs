@@ -1,10 +1,19 @@
 use serenity::{client::Context, model::id::GuildId};
 use tracing::instrument;
 
-use crate::utils::{SunnyError, SunnyResult};
+use crate::{
+    effects::fade,
+    structs::with_guild_state,
+    utils::{SunnyError, SunnyResult},
+};
 
+/// Stops playing and clears the queue, also turning autoplay off so it doesn't
+/// refill the queue right away
 #[instrument(skip(ctx))]
 pub async fn stop(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
+    with_guild_state(ctx, guild_id, |s| s.autoplay = None).await?;
+    fade::fade_out_all(ctx, guild_id).await?;
+
     songbird::get(ctx)
         .await
         .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
@@ -15,5 +24,5 @@ pub async fn stop(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
         .queue()
         .stop();
 
-    Ok(())
+    fade::stop_fading_out(ctx, guild_id).await
 }
@@ -1,11 +1,28 @@
-use std::cmp;
-
 use serenity::{client::Context, model::id::GuildId};
 use songbird::tracks::TrackHandle;
 use tracing::instrument;
 
 use crate::utils::{SunnyError, SunnyResult};
 
+/// Validates a 1-based song index into a queue of `len` songs, where index 0
+/// is the song that's currently playing.
+pub fn validate_index(index: usize, len: usize) -> SunnyResult<()> {
+    // What's this, a precondition, in my code!?
+    if index == 0 {
+        return Err(SunnyError::user(
+            "A song index of 0 is invalid (The queue starts at 1)",
+        ));
+    }
+
+    if index >= len {
+        return Err(SunnyError::user(
+            format!("There's no song at position {}", index).as_str(),
+        ));
+    }
+
+    Ok(())
+}
+
 #[instrument(skip(ctx))]
 pub async fn swap(
     ctx: &Context,
@@ -13,13 +30,6 @@ pub async fn swap(
     a: usize,
     b: usize,
 ) -> SunnyResult<(TrackHandle, TrackHandle)> {
-    // What's this, a precondition, in my code!?
-    if a == 0 || b == 0 {
-        return Err(SunnyError::user(
-            "A song index of 0 is invalid (The queue starts at 1)",
-        ));
-    }
-
     let call_m = songbird::get(ctx)
         .await
         .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
@@ -30,9 +40,8 @@ pub async fn swap(
 
     let q = call.queue();
 
-    if cmp::max(a, b) >= q.len() {
-        return Err(SunnyError::user("Can't swap non-existing index"));
-    }
+    validate_index(a, q.len())?;
+    validate_index(b, q.len())?;
 
     let (t1, t2) = q.modify_queue(|q| {
         q.swap(a, b);
@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+use serenity::{
+    client::Context,
+    model::id::{GuildId, UserId},
+};
+use tracing::{event, instrument, Level};
+
+use crate::{
+    structs::TrackRecord,
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{
+    display_queue::get_queue,
+    get_song, library, parse_url,
+    queue::{self, EnqueueAt},
+};
+
+/// Most songs a queue file may have, every song is loaded one by one
+const MAX_IMPORT: usize = 50;
+
+/// File formats a queue can be exported as and imported from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueFormat {
+    M3u,
+    Json,
+}
+
+impl QueueFormat {
+    /// Parses a format name or file extension
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim_start_matches('.').to_lowercase().as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub const fn file_name(self) -> &'static str {
+        match self {
+            Self::M3u => "queue.m3u",
+            Self::Json => "queue.json",
+        }
+    }
+}
+
+/// A track as it is written to a queue file
+#[derive(Debug, Deserialize, Serialize)]
+struct FileTrack {
+    url: String,
+    #[serde(default)]
+    title: Option<String>,
+    /// Duration in seconds
+    #[serde(default)]
+    duration: Option<u64>,
+}
+
+fn to_m3u(tracks: &[FileTrack]) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+
+    for t in tracks {
+        // -1 marks an unknown duration
+        let duration = t.duration.map_or(-1, |d| d as i64);
+        let title = t.title.as_deref().unwrap_or_default();
+
+        m3u.push_str(&format!("#EXTINF:{},{}\n{}\n", duration, title, t.url));
+    }
+
+    m3u
+}
+
+fn from_m3u(m3u: &str) -> Vec<FileTrack> {
+    let mut tracks = Vec::new();
+    let mut info: Option<(Option<u64>, String)> = None;
+
+    for line in m3u.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            info = extinf
+                .split_once(',')
+                .map(|(d, t)| (d.trim().parse().ok(), t.trim().to_string()));
+        } else if !line.starts_with('#') {
+            let (duration, title) = info.take().unwrap_or_default();
+
+            tracks.push(FileTrack {
+                url: line.to_string(),
+                title: Some(title).filter(|t| !t.is_empty()),
+                duration,
+            });
+        }
+    }
+
+    tracks
+}
+
+/// Exports the queue, current track first, returning the file contents
+#[instrument(skip(ctx))]
+pub async fn export_queue(
+    ctx: &Context,
+    guild_id: GuildId,
+    format: QueueFormat,
+) -> SunnyResult<Vec<u8>> {
+    let tracks: Vec<FileTrack> = get_queue(ctx, guild_id)
+        .await?
+        .iter()
+        .filter_map(|t| {
+            let m = t.metadata();
+
+            Some(FileTrack {
+                url: m.source_url.clone()?,
+                title: Some(get_song(m)),
+                duration: m.duration.map(|d| d.as_secs()),
+            })
+        })
+        .collect();
+
+    if tracks.is_empty() {
+        return Err(SunnyError::user("Nothing in the queue to export"));
+    }
+
+    match format {
+        QueueFormat::M3u => Ok(to_m3u(&tracks).into_bytes()),
+        QueueFormat::Json => serde_json::to_vec_pretty(&tracks)
+            .map_err(|e| SunnyError::log(format!("Failed to serialize queue: {}", e).as_str())),
+    }
+}
+
+/// Enqueues the tracks of an exported queue, tracks that fail to load are
+/// skipped.
+///
+/// Returns the number of enqueued tracks and the number of tracks in the file.
+#[instrument(skip(ctx, contents))]
+pub async fn import_queue(
+    ctx: &Context,
+    guild_id: GuildId,
+    contents: &[u8],
+    format: QueueFormat,
+    requester: UserId,
+) -> SunnyResult<(usize, usize)> {
+    let contents = std::str::from_utf8(contents)
+        .map_err(|_| SunnyError::user("That file isn't text, is it really a queue?"))?;
+
+    let tracks = match format {
+        QueueFormat::M3u => from_m3u(contents),
+        QueueFormat::Json => serde_json::from_str(contents).map_err(|e| {
+            SunnyError::user_and_log(
+                "Couldn't read that JSON queue",
+                format!("Invalid JSON queue: {}", e).as_str(),
+            )
+        })?,
+    };
+
+    let total = tracks.len();
+    if total > MAX_IMPORT {
+        return Err(SunnyError::user(
+            format!("Queue files can have at most {} songs", MAX_IMPORT).as_str(),
+        ));
+    }
+
+    let mut imported = 0;
+    for track in tracks {
+        // Exported library songs are paths, anything else has to be a url
+        let url = match parse_url(&track.url)
+            .or_else(|| library::in_library(&track.url).map(|p| p.to_string_lossy().to_string()))
+        {
+            Some(url) => url,
+            None => {
+                event!(Level::WARN, url = %track.url, "skipped invalid track url");
+                continue;
+            }
+        };
+
+        let record = TrackRecord::new(requester, "import");
+
+        if let Err(e) = queue::play(ctx, guild_id, url, EnqueueAt::Back, record).await {
+            event!(Level::WARN, %e, "failed to import track");
+            continue;
+        }
+
+        imported += 1;
+    }
+
+    Ok((imported, total))
+}
@@ -0,0 +1,412 @@
+use std::{collections::HashSet, sync::Mutex, time::Duration};
+
+use once_cell::sync::Lazy;
+use rand::{rngs::SmallRng, seq::SliceRandom, SeedableRng};
+use serenity::{
+    builder::CreateEmbed,
+    client::Context,
+    model::id::{GuildId, UserId},
+};
+use songbird::tracks::{PlayMode, TrackHandle};
+use tracing::{event, instrument, Level};
+
+use crate::{
+    db,
+    structs::{with_guild_state, Layer, TrackRecord},
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{
+    fade, mixer, playlist,
+    queue::{self, EnqueueAt},
+};
+
+/// Guilds a scene is being applied in right now, so two don't interleave
+static APPLYING: Lazy<Mutex<HashSet<GuildId>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
+/// A named mood: the music, the ambience under it and how loud the music is
+#[derive(Clone, Debug)]
+pub struct Scene {
+    pub name: String,
+    /// Server playlist replacing the queue, `None` keeps the music playing
+    pub playlist: Option<String>,
+    /// Whether the playlist is shuffled
+    pub shuffle: bool,
+    /// Ambience clip, `None` for no ambience
+    pub ambience: Option<String>,
+    /// Volume of the music, 1.0 being the volume of the source
+    pub volume: f32,
+}
+
+impl Scene {
+    /// A scene that doesn't change anything but the ambience, which it stops
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            playlist: None,
+            shuffle: false,
+            ambience: None,
+            volume: 1.0,
+        }
+    }
+}
+
+impl From<tokio_postgres::Row> for Scene {
+    fn from(row: tokio_postgres::Row) -> Self {
+        Self {
+            name: row.get("name"),
+            playlist: row.get("playlist"),
+            shuffle: row.get("shuffle"),
+            ambience: row.get("ambience"),
+            volume: f32::from(row.get::<_, i16>("volume")) / 100.0,
+        }
+    }
+}
+
+/// Finds a scene of the guild, names are case insensitive
+#[instrument]
+pub async fn get(guild_id: GuildId, name: &str) -> SunnyResult<Option<Scene>> {
+    Ok(db::connect()
+        .await?
+        .query_opt(
+            "SELECT name, playlist, shuffle, ambience, volume FROM scenes WHERE guild_id = $1 AND lower(name) = lower($2)",
+            &[&(guild_id.0 as i64), &name],
+        )
+        .await
+        .map_err(db::log_err)?
+        .map(Scene::from))
+}
+
+/// Lists the scenes of the guild
+#[instrument]
+pub async fn list(guild_id: GuildId) -> SunnyResult<Vec<Scene>> {
+    Ok(db::connect()
+        .await?
+        .query(
+            "SELECT name, playlist, shuffle, ambience, volume FROM scenes WHERE guild_id = $1 ORDER BY lower(name)",
+            &[&(guild_id.0 as i64)],
+        )
+        .await
+        .map_err(db::log_err)?
+        .into_iter()
+        .map(Scene::from)
+        .collect())
+}
+
+/// Saves a scene, replacing the one with the same name
+#[instrument]
+pub async fn save(guild_id: GuildId, scene: &Scene) -> SunnyResult<()> {
+    let volume = (scene.volume * 100.0).round() as i16;
+
+    db::connect()
+        .await?
+        .execute(
+            "INSERT INTO scenes (guild_id, name, playlist, shuffle, ambience, volume) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (guild_id, lower(name)) DO UPDATE SET playlist = $3, shuffle = $4, ambience = $5, volume = $6",
+            &[
+                &(guild_id.0 as i64),
+                &scene.name,
+                &scene.playlist,
+                &scene.shuffle,
+                &scene.ambience,
+                &volume,
+            ],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    Ok(())
+}
+
+/// Deletes a scene of the guild
+#[instrument]
+pub async fn delete(guild_id: GuildId, name: &str) -> SunnyResult<()> {
+    let deleted = db::connect()
+        .await?
+        .execute(
+            "DELETE FROM scenes WHERE guild_id = $1 AND lower(name) = lower($2)",
+            &[&(guild_id.0 as i64), &name],
+        )
+        .await
+        .map_err(db::log_err)?;
+
+    if deleted == 0 {
+        return Err(SunnyError::user(
+            format!("There's no scene called `{}`", name).as_str(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Marks a guild as applying a scene until dropped
+struct Applying(GuildId);
+
+impl Applying {
+    fn start(guild_id: GuildId) -> SunnyResult<Self> {
+        let mut applying = APPLYING
+            .lock()
+            .map_err(|_| SunnyError::log("Scene lock poisoned"))?;
+
+        if !applying.insert(guild_id) {
+            return Err(SunnyError::user("Already changing the scene"));
+        }
+
+        Ok(Self(guild_id))
+    }
+}
+
+impl Drop for Applying {
+    fn drop(&mut self) {
+        if let Ok(mut applying) = APPLYING.lock() {
+            applying.remove(&self.0);
+        }
+    }
+}
+
+/// Applies a scene, fading from what's playing into it over `fade`.
+///
+/// Everything the scene needs is looked up and its first song is sourced
+/// before anything changes, so a missing playlist or clip or a song that
+/// fails to load leaves the current scene as it is. The rest of the playlist
+/// is queued in the background.
+#[instrument(skip(ctx))]
+pub async fn apply(
+    ctx: &Context,
+    guild_id: GuildId,
+    name: &str,
+    fade: Option<Duration>,
+    requester: UserId,
+) -> SunnyResult<Scene> {
+    let scene = get(guild_id, name)
+        .await?
+        .ok_or_else(|| SunnyError::user(format!("There's no scene called `{}`", name).as_str()))?;
+
+    let mut urls: Vec<String> = match &scene.playlist {
+        Some(name) => playlist::tracks(guild_id, None, name)
+            .await?
+            .into_iter()
+            .map(|t| t.url)
+            .collect(),
+        None => Vec::new(),
+    };
+
+    if scene.playlist.is_some() && urls.is_empty() {
+        return Err(SunnyError::user(
+            format!("The playlist of `{}` is empty", scene.name).as_str(),
+        ));
+    }
+
+    if let Some(clip) = &scene.ambience {
+        mixer::find_clip(guild_id, clip)?;
+    }
+
+    if scene.shuffle {
+        urls.shuffle(&mut SmallRng::from_entropy());
+    }
+
+    let _applying = Applying::start(guild_id)?;
+    let fade = fade.unwrap_or_default();
+
+    // Queued right after the current song, it takes over further down
+    let mut urls = urls.into_iter();
+    let first = match urls.next() {
+        Some(url) => {
+            let record = TrackRecord::new(requester, "scene");
+            Some(queue::play(ctx, guild_id, url, EnqueueAt::Front, record).await?)
+        }
+        None => None,
+    };
+
+    let (ambience, music) = tokio::join!(
+        switch_ambience(ctx, guild_id, scene.ambience.as_deref(), fade),
+        switch_music(ctx, guild_id, first, scene.volume, fade),
+    );
+    ambience?;
+    music?;
+
+    // Sourcing a track takes a while, the scene is set without the rest
+    let rest: Vec<String> = urls.collect();
+    if !rest.is_empty() {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            for url in rest {
+                let record = TrackRecord::new(requester, "scene");
+
+                if let Err(e) = queue::play(&ctx, guild_id, url, EnqueueAt::Back, record).await {
+                    event!(Level::WARN, %e, "failed to load scene track");
+                }
+            }
+        });
+    }
+
+    Ok(scene)
+}
+
+/// Plays the scene's ambience, one that's already playing just keeps going
+async fn switch_ambience(
+    ctx: &Context,
+    guild_id: GuildId,
+    clip: Option<&str>,
+    fade: Duration,
+) -> SunnyResult<()> {
+    let (tracks, volume) = with_guild_state(ctx, guild_id, |s| {
+        let layer = s.layer(Layer::Ambience);
+        (layer.tracks.clone(), layer.volume)
+    })
+    .await?;
+
+    let clip = match clip {
+        Some(clip) => clip,
+        None => {
+            let tracks = with_guild_state(ctx, guild_id, |s| {
+                std::mem::take(&mut s.layer(Layer::Ambience).tracks)
+            })
+            .await?;
+
+            fade_out(tracks, volume, fade).await;
+            return Ok(());
+        }
+    };
+
+    let (name, _) = mixer::find_clip(guild_id, clip)?;
+    let playing = tracks
+        .iter()
+        .any(|t| matches!(&t.metadata().track, Some(track) if track.eq_ignore_ascii_case(&name)));
+
+    if playing {
+        return Ok(());
+    }
+
+    let (_, handle) = mixer::play_clip(ctx, guild_id, Layer::Ambience, &name).await?;
+    if !fade.is_zero() && handle.set_volume(0.0).is_ok() {
+        tokio::spawn(fade::ramp(handle, 0.0, volume, fade));
+    }
+
+    Ok(())
+}
+
+/// Ramps tracks down to silence and stops them
+async fn fade_out(tracks: Vec<TrackHandle>, volume: f32, fade: Duration) {
+    if !fade.is_zero() {
+        let ramps: Vec<_> = tracks
+            .iter()
+            .map(|t| tokio::spawn(fade::ramp(t.clone(), volume, 0.0, fade)))
+            .collect();
+
+        for ramp in ramps {
+            let _ = ramp.await;
+        }
+    }
+
+    for track in tracks {
+        let _ = track.stop();
+    }
+}
+
+/// Replaces the queue with the scene's first song, queued at `first`, or
+/// only changes the volume of the music if the scene has no playlist
+async fn switch_music(
+    ctx: &Context,
+    guild_id: GuildId,
+    first: Option<usize>,
+    volume: f32,
+    fade: Duration,
+) -> SunnyResult<()> {
+    let (old_volume, volume) = with_guild_state(ctx, guild_id, |s| {
+        let old = s.music_volume();
+        s.layer(Layer::Music).volume = volume;
+
+        // The scene's playlist takes over from autoplay
+        if first.is_some() {
+            s.autoplay = None;
+        }
+
+        (old, s.music_volume())
+    })
+    .await?;
+
+    let queue = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?
+        .lock()
+        .await
+        .queue()
+        .clone();
+
+    let first = match first.and_then(|i| queue.current_queue().get(i).cloned()) {
+        Some(first) => first,
+        None => {
+            for track in queue.current_queue() {
+                match track.get_info().await {
+                    Ok(info) if info.playing == PlayMode::Play && !fade.is_zero() => {
+                        tokio::spawn(fade::ramp(track, old_volume, volume, fade));
+                    }
+                    _ => {
+                        let _ = track.set_volume(volume);
+                    }
+                }
+            }
+
+            return Ok(());
+        }
+    };
+
+    queue::clear_upcoming_but(ctx, guild_id, first.uuid()).await?;
+    let _ = first.set_volume(volume);
+
+    // Something was playing, the scene's first song takes over from it
+    if let Some(current) = queue.current().filter(|t| t.uuid() != first.uuid()) {
+        let handed_over =
+            !fade.is_zero() && fade::hand_over(ctx, guild_id, &queue, &current, fade).await;
+
+        if !handed_over {
+            queue
+                .skip()
+                .map_err(|e| SunnyError::log(format!("Failed to skip to scene: {}", e).as_str()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Describes what a scene does, for `scene list`
+pub fn describe(scene: &Scene) -> String {
+    let music = match &scene.playlist {
+        Some(p) if scene.shuffle => format!("playlist `{}` shuffled", p),
+        Some(p) => format!("playlist `{}`", p),
+        None => "the current music".to_string(),
+    };
+
+    let ambience = match &scene.ambience {
+        Some(a) => format!("`{}` ambience", a),
+        None => "no ambience".to_string(),
+    };
+
+    format!(
+        "{} at {}%, {}",
+        music,
+        (scene.volume * 100.0).round(),
+        ambience
+    )
+}
+
+/// Generates an embed listing the guild's scenes
+pub fn generate_embed(scenes: &[Scene]) -> CreateEmbed {
+    let mut e = CreateEmbed::default();
+    e.author(|a| a.name("Scenes"));
+
+    let lines: Vec<String> = scenes
+        .iter()
+        .map(|s| format!("**{}** - {}", s.name, describe(s)))
+        .collect();
+
+    if lines.is_empty() {
+        e.description("No scenes yet, DMs can make one with `scene set`");
+    } else {
+        e.description(lines.join("\n"));
+    }
+
+    e
+}
@@ -0,0 +1,245 @@
+use std::time::Duration;
+
+use serenity::{
+    builder::{CreateActionRow, CreateEmbed},
+    client::Context,
+    futures::prelude::*,
+    model::{
+        channel::Message,
+        id::{ChannelId, GuildId},
+        interactions::{
+            message_component::{ButtonStyle, MessageComponentInteraction},
+            InteractionResponseType,
+        },
+    },
+};
+use tracing::{event, instrument, Level};
+
+use crate::{
+    checks,
+    structs::Layer,
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{mixer, reply_ephemeral};
+
+const CLIP_PREFIX: &str = "sb_clip_";
+const PREV_ID: &str = "sb_prev";
+const NEXT_ID: &str = "sb_next";
+
+/// Rows of clip buttons, the last row Discord allows is for paging
+const ROWS: usize = 4;
+
+/// Buttons per row, the most Discord allows
+const ROW_LEN: usize = 5;
+
+const PAGE_LEN: usize = ROWS * ROW_LEN;
+
+fn pages(clips: usize) -> usize {
+    clips.div_ceil(PAGE_LEN).max(1)
+}
+
+fn generate_embed(clips: &[String], page: usize) -> CreateEmbed {
+    let mut e = CreateEmbed::default();
+    e.author(|a| a.name("Soundboard"));
+
+    if clips.is_empty() {
+        e.description("There are no clips, DMs can upload some with `soundboard add`");
+    } else {
+        e.description("Press a clip to play it over the music");
+    }
+
+    e.footer(|f| {
+        f.text(format!(
+            "Page {}/{} | {} clips",
+            page + 1,
+            pages(clips.len()),
+            clips.len()
+        ))
+    });
+
+    e
+}
+
+fn build_action_rows(clips: &[String], page: usize) -> Vec<CreateActionRow> {
+    let mut rows: Vec<CreateActionRow> = clips
+        .iter()
+        .enumerate()
+        .skip(page * PAGE_LEN)
+        .take(PAGE_LEN)
+        .collect::<Vec<_>>()
+        .chunks(ROW_LEN)
+        .map(|chunk| {
+            let mut row = CreateActionRow::default();
+            for (i, name) in chunk {
+                row.create_button(|b| {
+                    b.style(ButtonStyle::Secondary);
+                    b.label(name);
+                    b.custom_id(format!("{}{}", CLIP_PREFIX, i))
+                });
+            }
+            row
+        })
+        .collect();
+
+    if pages(clips.len()) > 1 {
+        let mut row = CreateActionRow::default();
+        row.create_button(|b| {
+            b.style(ButtonStyle::Primary);
+            b.label("Previous");
+            b.custom_id(PREV_ID);
+            b.disabled(page == 0)
+        });
+        row.create_button(|b| {
+            b.style(ButtonStyle::Primary);
+            b.label("Next");
+            b.custom_id(NEXT_ID);
+            b.disabled(page + 1 >= pages(clips.len()))
+        });
+        rows.push(row);
+    }
+
+    rows
+}
+
+/// Names of the guild's clips, in the order of the buttons
+fn clip_names(guild_id: GuildId) -> SunnyResult<Vec<String>> {
+    Ok(mixer::clips(guild_id)?
+        .into_iter()
+        .map(|(n, _)| n)
+        .collect())
+}
+
+/// Sends a soundboard with a button for every clip
+#[instrument(skip(ctx), name = "soundboard_embed")]
+pub async fn send_embed(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> SunnyResult<()> {
+    let clips = clip_names(guild_id)?;
+
+    let message = channel_id
+        .send_message(&ctx.http, |m| {
+            m.components(|c| c.set_action_rows(build_action_rows(&clips, 0)));
+            m.set_embed(generate_embed(&clips, 0))
+        })
+        .await
+        .map_err(|e| {
+            SunnyError::log(format!("Unable to send soundboard message: {:?}", e).as_str())
+        })?;
+
+    await_interactions(ctx, message, guild_id, clips).await
+}
+
+/// Plays a clip over the music for whoever pressed its button, if they're in
+/// the call
+async fn play(
+    ctx: &Context,
+    mci: &MessageComponentInteraction,
+    guild_id: GuildId,
+    name: &str,
+) -> SunnyResult<()> {
+    let played = match checks::in_same_voice(ctx, guild_id, mci.user.id).await {
+        Ok(()) => mixer::play_clip(ctx, guild_id, Layer::Sfx, name).await,
+        Err(e) => Err(e),
+    };
+
+    let (_, clip) = match played {
+        Ok(played) => played,
+        Err(e) => {
+            let content = e.user_message().unwrap_or("Couldn't play that clip");
+            reply_ephemeral(ctx, mci, content).await?;
+            return Err(e);
+        }
+    };
+
+    mci.create_interaction_response(&ctx.http, |cir| {
+        cir.kind(InteractionResponseType::DeferredUpdateMessage)
+    })
+    .await
+    .map_err(|e| {
+        SunnyError::log(format!("Unable to create interaction response: {:?}", e).as_str())
+    })?;
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        if let Err(e) = mixer::duck_under(&ctx, guild_id, clip).await {
+            event!(Level::WARN, %e, "unable to duck music");
+        }
+    });
+
+    Ok(())
+}
+
+async fn await_interactions(
+    ctx: &Context,
+    mut msg: Message,
+    guild_id: GuildId,
+    mut clips: Vec<String>,
+) -> SunnyResult<()> {
+    // Currently shown page
+    let mut page: usize = 0;
+
+    let mut collector = msg
+        .await_component_interactions(&ctx.shard)
+        .timeout(Duration::from_secs(3600)) // 1h
+        .await;
+
+    while let Some(mci) = collector.next().await {
+        let id = mci.data.custom_id.as_str();
+
+        if let Some(index) = id.strip_prefix(CLIP_PREFIX) {
+            let name = match index.parse::<usize>().ok().and_then(|i| clips.get(i)) {
+                Some(name) => name,
+                None => continue,
+            };
+
+            // One clip failing shouldn't take the whole soundboard down
+            if let Err(e) = play(ctx, &mci, guild_id, name).await {
+                event!(Level::INFO, %e, clip = %name, "clip not played");
+            }
+            continue;
+        }
+
+        if id == NEXT_ID {
+            page += 1;
+        } else if id == PREV_ID {
+            page = if let Some(p) = page.checked_sub(1) {
+                p
+            } else {
+                continue;
+            };
+        } else {
+            continue;
+        }
+
+        // Clips may have been added or removed since
+        if let Ok(current) = clip_names(guild_id) {
+            clips = current;
+        }
+        page = page.min(pages(clips.len()) - 1);
+
+        mci.create_interaction_response(&ctx.http, |cir| {
+            cir.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|m| {
+                    m.add_embed(generate_embed(&clips, page));
+                    m.components(|c| c.set_action_rows(build_action_rows(&clips, page)))
+                })
+        })
+        .await
+        .map_err(|e| {
+            SunnyError::log(format!("Unable to create interaction response: {:?}", e).as_str())
+        })?;
+    }
+
+    // Remove buttons after timeout
+    msg.edit(&ctx.http, |e| {
+        e.components(|c| c);
+        e.set_embed(generate_embed(&clips, page))
+    })
+    .await
+    .map_err(|e| SunnyError::log(format!("Unable clear buttons {:?}", e).as_str()))?;
+
+    Ok(())
+}
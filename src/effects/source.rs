@@ -0,0 +1,366 @@
+use std::{
+    io::Error as IoError,
+    process::{Command, Stdio},
+    sync::{Arc, PoisonError},
+    time::Duration,
+};
+
+use serenity::{async_trait, client::Context, model::id::GuildId};
+use songbird::{
+    input::{
+        error::{Error as InputError, Result as InputResult},
+        ffmpeg_optioned,
+        restartable::Restart,
+        Codec, Container, Input, Metadata, Reader, Restartable,
+    },
+    tracks::TrackHandle,
+};
+use tokio::process::Command as TokioCommand;
+use tracing::instrument;
+
+use crate::{
+    structs::{AudioSettings, SharedAudioSettings, SharedClock, SourceClock, TrackClock},
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{
+    library, loudness,
+    probe::{self, cover_art},
+    source_error::source_error,
+};
+
+/// Same format selection songbird uses for its ytdl sources
+pub const YTDL_ARGS: &[&str] = &[
+    "-f",
+    "webm[abr>0]/bestaudio/best",
+    "-R",
+    "infinite",
+    "--no-playlist",
+    "--ignore-config",
+    "--no-warnings",
+];
+
+/// Length of an audio frame songbird plays
+const FRAME: Duration = Duration::from_millis(20);
+
+/// Raw stereo float output songbird expects from ffmpeg
+const PCM_ARGS: &[&str] = &[
+    "-f",
+    "s16le",
+    "-ac",
+    "2",
+    "-ar",
+    "48000",
+    "-acodec",
+    "pcm_f32le",
+    "-",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceKind {
+    /// Pages ytdl extracts the audio from
+    Ytdl,
+    /// Files and direct urls ffmpeg opens itself
+    File,
+}
+
+/// Recreates a source with the guild's current [`AudioSettings`]
+/// every time it starts or seeks.
+struct SunnyRestarter {
+    uri: String,
+    kind: SourceKind,
+    audio: SharedAudioSettings,
+    clock: SharedClock,
+}
+
+impl SunnyRestarter {
+    /// Starts measuring the loudness of the source right away when the guild
+    /// normalizes, so it's likely cached by the time the track plays
+    fn new(uri: String, kind: SourceKind, audio: SharedAudioSettings) -> Self {
+        let (normalize, tempo) = match audio.read() {
+            Ok(a) => (a.loudness_target.is_some(), a.tempo()),
+            Err(_) => (false, 1.0),
+        };
+        if normalize {
+            loudness::measure_in_background(kind, uri.clone());
+        }
+
+        Self {
+            uri,
+            kind,
+            audio,
+            clock: Arc::new(std::sync::Mutex::new(SourceClock::new(tempo))),
+        }
+    }
+
+    /// Anchors the clock where the source restarts at `tempo`, returning the
+    /// time in the source to start from. Songbird seeks to a position, which
+    /// ran at the tempo the track played at so far.
+    fn restart_clock(&self, position: Option<Duration>, tempo: f64) -> Option<Duration> {
+        let mut clock = self.clock.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let source = position.map(|p| clock.source_time(p));
+        *clock = SourceClock {
+            position: position.unwrap_or_default(),
+            source: source.unwrap_or_default(),
+            tempo,
+        };
+
+        source
+    }
+
+    /// ffmpeg arguments for the output, including the audio filters
+    async fn output_args(&self, settings: &AudioSettings) -> Vec<String> {
+        let mut filters = Vec::new();
+
+        // Normalize first, the measurements are of the unfiltered source
+        if let Some(target) = settings.loudness_target {
+            let measured = loudness::cached(&self.uri).await.unwrap_or_default();
+            if measured.is_none() {
+                loudness::measure_in_background(self.kind, self.uri.clone());
+            }
+
+            filters.push(loudness::ffmpeg_filter(target, measured));
+        }
+
+        if let Some(filter) = settings.filter {
+            filters.push(filter.ffmpeg_filter().to_string());
+        }
+
+        let mut args = Vec::new();
+        if !filters.is_empty() {
+            args.push("-af".to_string());
+            args.push(filters.join(","));
+        }
+
+        args.extend(PCM_ARGS.iter().map(|a| (*a).to_string()));
+        args
+    }
+}
+
+fn seek_args(time: Option<Duration>) -> Vec<String> {
+    time.map(|t| vec!["-ss".to_string(), format!("{:.3}", t.as_secs_f64())])
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl Restart for SunnyRestarter {
+    async fn call_restart(&mut self, time: Option<Duration>) -> InputResult<Input> {
+        let settings = self.audio.read().map(|a| a.clone()).unwrap_or_default();
+
+        let pre_args = seek_args(self.restart_clock(time, settings.tempo()));
+        let args = self.output_args(&settings).await;
+
+        match self.kind {
+            SourceKind::File => {
+                let pre_args: Vec<&str> = pre_args.iter().map(String::as_str).collect();
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+                ffmpeg_optioned(&self.uri, &pre_args, &args).await
+            }
+            SourceKind::Ytdl => {
+                let mut ytdl = Command::new("youtube-dl")
+                    .args(YTDL_ARGS)
+                    .args(["-o", "-", "--", self.uri.as_str()])
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+
+                let stdout = ytdl.stdout.take().ok_or(InputError::Stdout)?;
+
+                let ffmpeg = Command::new("ffmpeg")
+                    .args(&pre_args)
+                    .args(["-i", "-"])
+                    .args(&args)
+                    .stdin(stdout)
+                    .stderr(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .spawn()?;
+
+                Ok(Input::new(
+                    true,
+                    Reader::from(vec![ytdl, ffmpeg]),
+                    Codec::FloatPcm,
+                    Container::Raw,
+                    None,
+                ))
+            }
+        }
+    }
+
+    async fn lazy_init(&mut self) -> InputResult<(Option<Metadata>, Codec, Container)> {
+        let output = match self.kind {
+            SourceKind::File => {
+                TokioCommand::new("ffprobe")
+                    .args([
+                        "-v",
+                        "quiet",
+                        "-of",
+                        "json",
+                        "-show_format",
+                        "-show_streams",
+                    ])
+                    .arg(&self.uri)
+                    .stdin(Stdio::null())
+                    .output()
+                    .await?
+            }
+            SourceKind::Ytdl => {
+                TokioCommand::new("youtube-dl")
+                    .arg("-j")
+                    .args(YTDL_ARGS)
+                    .arg("--")
+                    .arg(&self.uri)
+                    .stdin(Stdio::null())
+                    .output()
+                    .await?
+            }
+        };
+
+        if !output.status.success() {
+            return Err(match self.kind {
+                SourceKind::Ytdl => InputError::YouTubeDlRun(output),
+                SourceKind::File => InputError::Io(IoError::other(
+                    String::from_utf8_lossy(&output.stderr).to_string(),
+                )),
+            });
+        }
+
+        let value = serde_json::from_slice(&output.stdout).map_err(|error| InputError::Json {
+            error,
+            parsed_text: String::from_utf8_lossy(&output.stdout).to_string(),
+        })?;
+
+        let metadata = match self.kind {
+            SourceKind::File => Metadata::from_ffprobe_json(&value),
+            SourceKind::Ytdl => Metadata::from_ytdl_output(value),
+        };
+
+        Ok((Some(metadata), Codec::FloatPcm, Container::Raw))
+    }
+}
+
+/// Creates a lazy ffmpeg source for a file or direct url, with the metadata
+/// taken from its tags and `fallback_title` for files without a title tag.
+async fn file_source(
+    uri: String,
+    fallback_title: String,
+    audio: SharedAudioSettings,
+) -> SunnyResult<(Input, Option<Vec<u8>>, SharedClock)> {
+    let restarter = SunnyRestarter::new(uri.clone(), SourceKind::File, audio);
+    let clock = restarter.clock.clone();
+
+    let mut source: Input = Restartable::new(restarter, true)
+        .await
+        .map_err(|e| source_error(&uri, &e))?
+        .into();
+
+    let tags = probe::probe(&uri).await.unwrap_or_default();
+
+    let m = &mut source.metadata;
+    m.track = Some(tags.title.unwrap_or(fallback_title));
+    m.artist = tags.artist.or_else(|| m.artist.take());
+    m.duration = tags.duration.or(m.duration);
+    m.source_url = Some(uri.clone());
+
+    Ok((source, cover_art(&uri).await, clock))
+}
+
+/// Creates the source of `url`: files in the library and direct links to
+/// audio files are opened with ffmpeg, anything else goes through ytdl.
+///
+/// Also returns the embedded cover art of files, if any, and the clock of the
+/// source.
+#[instrument(skip(audio))]
+pub async fn create_source(
+    url: &str,
+    audio: SharedAudioSettings,
+) -> SunnyResult<(Input, Option<Vec<u8>>, SharedClock)> {
+    if let Some(path) = library::in_library(url) {
+        let title = library::file_title(&path);
+
+        return file_source(path.to_string_lossy().to_string(), title, audio).await;
+    }
+
+    if let Some(direct) = probe::direct_url(url) {
+        let title = probe::url_file_title(&direct).unwrap_or_else(|| url.to_string());
+
+        return file_source(url.to_string(), title, audio).await;
+    }
+
+    let restarter = SunnyRestarter::new(url.to_string(), SourceKind::Ytdl, audio);
+    let clock = restarter.clock.clone();
+
+    let source = Restartable::new(restarter, true)
+        .await
+        .map_err(|e| source_error(url, &e))?;
+
+    Ok((source.into(), None, clock))
+}
+
+/// Clock of a track, tracks without one play at the pace of their source
+async fn clock(track: &TrackHandle) -> SourceClock {
+    track
+        .typemap()
+        .read()
+        .await
+        .get::<TrackClock>()
+        .map(|c| *c.lock().unwrap_or_else(PoisonError::into_inner))
+        .unwrap_or(SourceClock::new(1.0))
+}
+
+/// Position at which `track` ends, that's its duration at the tempo it plays
+/// at. Tracks without a known duration (like live streams) have no end.
+pub async fn end_position(track: &TrackHandle) -> Option<Duration> {
+    let duration = track.metadata().duration?;
+
+    Some(clock(track).await.position_at(duration))
+}
+
+/// Time in the source of `track` at `position`
+pub async fn source_time(track: &TrackHandle, position: Duration) -> Duration {
+    clock(track).await.source_time(position)
+}
+
+/// Position of `track` at `source` time in its source
+pub async fn position_at(track: &TrackHandle, source: Duration) -> Duration {
+    clock(track).await.position_at(source)
+}
+
+/// Recreates the current track's source at its position, so changed
+/// [`AudioSettings`](crate::structs::AudioSettings) apply right away
+#[instrument(skip(ctx))]
+pub async fn restart_current(ctx: &Context, guild_id: GuildId) -> SunnyResult<()> {
+    let current = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?
+        .lock()
+        .await
+        .queue()
+        .current();
+
+    let current = if let Some(current) = current {
+        current
+    } else {
+        return Ok(());
+    };
+
+    let position = current
+        .get_info()
+        .await
+        .map_err(|e| SunnyError::log(format!("Failed to get track info: {}", e).as_str()))?
+        .position;
+
+    // Only seeking backwards recreates the source, one frame is enough
+    current
+        .seek_time(position.saturating_sub(FRAME))
+        .map_err(|e| {
+            SunnyError::user_and_log(
+                "Failed to apply that to the current song",
+                format!("Failed to restart track: {}", e).as_str(),
+            )
+        })
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+
+use songbird::input::error::Error as InputError;
+use tracing::{event, Level};
+
+use crate::utils::SunnyError;
+
+/// Why ytdl or ffmpeg couldn't open a source, worked out from their output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceErrorKind {
+    AgeRestricted,
+    Private,
+    GeoBlocked,
+    Removed,
+    UnsupportedSite,
+    ExtractorFailure,
+    Unknown,
+}
+
+/// Phrases ytdl and ffmpeg use for each kind of error, checked in order
+const PATTERNS: &[(SourceErrorKind, &[&str])] = &[
+    (
+        SourceErrorKind::AgeRestricted,
+        &[
+            "confirm your age",
+            "age-restricted",
+            "age restricted",
+            "inappropriate for some users",
+        ],
+    ),
+    (
+        SourceErrorKind::Private,
+        &[
+            "private video",
+            "video is private",
+            "members-only",
+            "join this channel",
+            "requires authentication",
+        ],
+    ),
+    (
+        SourceErrorKind::GeoBlocked,
+        &[
+            "not available in your country",
+            "available from your location",
+            "geo restrict",
+            "geo-restrict",
+            "blocked it in your country",
+        ],
+    ),
+    (
+        SourceErrorKind::Removed,
+        &[
+            "video unavailable",
+            "has been removed",
+            "has been terminated",
+            "copyright claim",
+            "no longer available",
+            "does not exist",
+            "http error 404",
+            "404 not found",
+            "no such file or directory",
+        ],
+    ),
+    (SourceErrorKind::UnsupportedSite, &["unsupported url"]),
+    (
+        SourceErrorKind::ExtractorFailure,
+        &[
+            "unable to extract",
+            "unable to download",
+            "extractorerror",
+            "please report this issue",
+            "invalid data found when processing input",
+        ],
+    ),
+];
+
+impl SourceErrorKind {
+    /// Classifies the error output of ytdl or ffmpeg
+    pub fn classify(output: &str) -> Self {
+        let output = output.to_lowercase();
+
+        PATTERNS
+            .iter()
+            .find(|(_, phrases)| phrases.iter().any(|p| output.contains(p)))
+            .map_or(Self::Unknown, |(kind, _)| *kind)
+    }
+
+    pub const fn user_message(self) -> &'static str {
+        match self {
+            Self::AgeRestricted => "That video is age restricted, I can't play it :underage:",
+            Self::Private => "That video is private or members only :lock:",
+            Self::GeoBlocked => "That video isn't available in my country :earth_africa:",
+            Self::Removed => "That video doesn't exist (anymore) :ghost:",
+            Self::UnsupportedSite => "I don't know how to play songs from that site",
+            Self::ExtractorFailure => "I couldn't get the song out of that site, try again later",
+            Self::Unknown => "Error starting stream",
+        }
+    }
+}
+
+impl fmt::Display for SourceErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::AgeRestricted => "age_restricted",
+            Self::Private => "private",
+            Self::GeoBlocked => "geo_blocked",
+            Self::Removed => "removed",
+            Self::UnsupportedSite => "unsupported_site",
+            Self::ExtractorFailure => "extractor_failure",
+            Self::Unknown => "unknown",
+        };
+
+        write!(f, "{}", kind)
+    }
+}
+
+/// The output ytdl or ffmpeg left behind in an input error
+fn error_output(e: &InputError) -> String {
+    match e {
+        InputError::Json { parsed_text, .. } => parsed_text.clone(),
+        InputError::YouTubeDlRun(output) => String::from_utf8_lossy(&output.stderr).to_string(),
+        InputError::YouTubeDlProcessing(value) | InputError::YouTubeDlUrl(value) => {
+            value.to_string()
+        }
+        e => e.to_string(),
+    }
+}
+
+/// The line of the output that says what went wrong, if there is one
+fn error_line(output: &str) -> &str {
+    output
+        .lines()
+        .map(str::trim)
+        .find(|l| l.to_lowercase().contains("error"))
+        .unwrap_or_else(|| output.trim())
+}
+
+/// Classifies a failure to open `url`, logging it and turning it into an
+/// error with a message for the user
+pub fn source_error(url: &str, e: &InputError) -> SunnyError {
+    let output = error_output(e);
+    let kind = SourceErrorKind::classify(&output);
+    let reason = error_line(&output);
+
+    event!(Level::WARN, %kind, %url, %reason, "unable to open source");
+
+    SunnyError::user_and_log(
+        kind.user_message(),
+        format!("Error sourcing {} ({}): {}", url, kind, reason).as_str(),
+    )
+}
@@ -0,0 +1,182 @@
+use std::time::Duration;
+
+use serenity::{
+    client::Context,
+    model::id::{ChannelId, GuildId},
+};
+use songbird::tracks::{PlayMode, TrackHandle, TrackState};
+use tracing::{event, instrument, Level};
+
+use crate::{
+    structs::with_guild_state,
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::{
+    get_song,
+    queue::{self, EnqueueAt},
+    source::{end_position, position_at, source_time},
+};
+
+/// How long before its end a track may stop without its stream counting as died
+const END_SLACK: Duration = Duration::from_secs(5);
+
+/// Whether a track that stopped at `position` was short of its `end`. Tracks
+/// without a known end (like live streams) can't tell.
+fn ended_early(position: Duration, end: Option<Duration>) -> bool {
+    matches!(end, Some(end) if position + END_SLACK < end)
+}
+
+/// Whether a track ended on its own before reaching its end, which is how a
+/// stream dying partway through shows up.
+pub async fn died(state: &TrackState, track: &TrackHandle) -> bool {
+    state.playing == PlayMode::End && ended_early(state.position, end_position(track).await)
+}
+
+async fn report(ctx: &Context, channel_id: ChannelId, message: String) -> SunnyResult<()> {
+    channel_id
+        .say(&ctx.http, message)
+        .await
+        .map_err(|e| SunnyError::log(format!("Failed to report track error: {}", e).as_str()))?;
+
+    Ok(())
+}
+
+/// Retries a track whose stream died from where it stopped, a track is only
+/// retried once and otherwise skipped.
+///
+/// Returns whether the track is retried.
+#[instrument(skip(ctx, state))]
+pub async fn recover(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    state: &TrackState,
+    track: &TrackHandle,
+) -> SunnyResult<bool> {
+    let uuid = track.uuid();
+    let m = track.metadata();
+    let title = get_song(m);
+    let stopped_at = source_time(track, state.position).await;
+
+    let (record, retried, history_id) = with_guild_state(ctx, guild_id, |s| {
+        (
+            s.records.get(&uuid).cloned(),
+            s.retried.remove(&uuid),
+            s.history_ids.get(&uuid).copied(),
+        )
+    })
+    .await?;
+
+    let retry = match (&m.source_url, record) {
+        (Some(url), Some(record)) if !retried => Some((url.clone(), record)),
+        _ => None,
+    };
+
+    let (url, record) = if let Some(retry) = retry {
+        retry
+    } else {
+        event!(Level::WARN, %title, position = ?state.position, "stream died again, skipping");
+        report(
+            ctx,
+            channel_id,
+            format!("Couldn't get {} back, skipping it :fast_forward:", title),
+        )
+        .await?;
+
+        return Ok(false);
+    };
+
+    event!(Level::WARN, %title, position = ?state.position, "stream died, retrying");
+    report(
+        ctx,
+        channel_id,
+        format!(
+            "Lost the stream of {}, retrying :arrows_counterclockwise:",
+            title
+        ),
+    )
+    .await?;
+
+    let position = match queue::play(ctx, guild_id, url, EnqueueAt::Front, record).await {
+        Ok(position) => position,
+        Err(e) => {
+            event!(Level::WARN, %e, %title, "retry failed, skipping");
+            report(
+                ctx,
+                channel_id,
+                format!("Couldn't get {} back, skipping it :fast_forward:", title),
+            )
+            .await?;
+
+            return Ok(false);
+        }
+    };
+
+    let call_m = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?;
+
+    let call = call_m.lock().await;
+
+    // The next track already took over, put it back behind the retry
+    let retry = call.queue().modify_queue(|q| {
+        let retry = q.remove(position)?;
+        q.push_front(retry);
+
+        if let Some(next) = q.get(1) {
+            next.pause().ok();
+            next.seek_time(Duration::default()).ok();
+        }
+
+        q.front().map(|t| t.handle())
+    });
+
+    let retry = retry.ok_or_else(|| SunnyError::log("Retried track disappeared"))?;
+
+    // The retry continues the original's history entry
+    with_guild_state(ctx, guild_id, |s| {
+        s.retried.insert(retry.uuid());
+
+        if let Some(id) = history_id {
+            s.history_ids.insert(retry.uuid(), id);
+        }
+    })
+    .await?;
+
+    let position = position_at(&retry, stopped_at).await;
+    retry
+        .play()
+        .and_then(|_| retry.seek_time(position))
+        .map_err(|e| SunnyError::log(format!("Failed to resume retry: {}", e).as_str()))?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn stopping_midway_is_early() {
+        assert!(ended_early(MINUTE, Some(3 * MINUTE)));
+    }
+
+    #[test]
+    fn stopping_near_the_end_is_not_early() {
+        assert!(!ended_early(3 * MINUTE, Some(3 * MINUTE)));
+        assert!(!ended_early(
+            3 * MINUTE - Duration::from_secs(2),
+            Some(3 * MINUTE)
+        ));
+    }
+
+    #[test]
+    fn unknown_duration_is_never_early() {
+        assert!(!ended_early(Duration::ZERO, None));
+    }
+}
@@ -0,0 +1,228 @@
+use std::{collections::HashSet, time::Duration};
+
+use serenity::{
+    builder::CreateActionRow,
+    client::Context,
+    futures::prelude::*,
+    model::{
+        guild::Guild,
+        id::{ChannelId, GuildId, UserId},
+        interactions::{message_component::ButtonStyle, InteractionResponseType},
+    },
+};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    checks,
+    structs::with_guild_state,
+    utils::{SunnyError, SunnyResult},
+};
+
+use super::queue;
+
+const VOTE_ID: &str = "vote_skip";
+
+/// How long a vote stays open
+const VOTE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Lists the non-bot members listening in `channel_id`, walking the voice
+/// states the same way the timeout handler checks if Sunny is alone.
+pub fn listeners(guild: &Guild, channel_id: ChannelId, bot_id: UserId) -> Vec<UserId> {
+    guild
+        .voice_states
+        .values()
+        .filter(|vs| match vs.channel_id {
+            Some(c_id) => channel_id.0 == c_id.0 && vs.user_id.0 != bot_id.0,
+            None => false,
+        })
+        .filter(|vs| {
+            let member = vs
+                .member
+                .as_ref()
+                .or_else(|| guild.members.get(&vs.user_id));
+
+            !matches!(member, Some(m) if m.user.bot)
+        })
+        .map(|vs| vs.user_id)
+        .collect()
+}
+
+/// Number of votes needed to skip with `listeners` people listening
+fn votes_needed(listeners: usize, fraction: f32) -> usize {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    let needed = (listeners as f32 * fraction).ceil() as usize;
+
+    needed.max(1)
+}
+
+fn build_action_row(votes: usize, needed: usize, disabled: bool) -> CreateActionRow {
+    let mut row = CreateActionRow::default();
+
+    row.create_button(|b| {
+        b.style(ButtonStyle::Primary);
+        b.label(format!("Skip ({}/{})", votes, needed));
+        b.custom_id(VOTE_ID);
+        b.disabled(disabled)
+    });
+
+    row
+}
+
+/// Gets the current track's uuid and Sunny's voice channel
+async fn current(ctx: &Context, guild_id: GuildId) -> SunnyResult<(Option<Uuid>, ChannelId)> {
+    let call_m = songbird::get(ctx)
+        .await
+        .ok_or_else(|| SunnyError::log("Couldn't get songbird"))?
+        .get(guild_id)
+        .ok_or_else(|| SunnyError::log("No Call"))?;
+
+    let call = call_m.lock().await;
+
+    let channel = call
+        .current_channel()
+        .ok_or_else(|| SunnyError::log("Couldn't find songbird channel"))?;
+
+    Ok((
+        call.queue().current().map(|t| t.uuid()),
+        ChannelId(channel.0),
+    ))
+}
+
+/// Starts a vote to skip the current track, collecting votes through a button
+/// from the listeners in Sunny's channel.
+///
+/// Returns the queue length after skipping, or `None` if the vote failed.
+#[instrument(skip(ctx))]
+pub async fn vote_skip(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    initiator: UserId,
+    fraction: f32,
+) -> SunnyResult<Option<usize>> {
+    let (track, voice_channel_id) = current(ctx, guild_id).await?;
+    let track = track.ok_or_else(|| SunnyError::user("No song playing"))?;
+
+    let bot_id = ctx.cache.current_user_id().await;
+    let count_listeners = || async {
+        ctx.cache
+            .guild(guild_id)
+            .await
+            .map(|g| listeners(&g, voice_channel_id, bot_id))
+            .ok_or_else(|| SunnyError::log("Couldn't get guild"))
+    };
+
+    let mut votes = HashSet::new();
+    votes.insert(initiator);
+
+    let mut needed = votes_needed(count_listeners().await?.len(), fraction);
+
+    if votes.len() >= needed {
+        return queue::skip(ctx, guild_id).await.map(Some);
+    }
+
+    let mut msg = channel_id
+        .send_message(&ctx.http, |m| {
+            m.content(format!(
+                "<@{}> wants to skip this song, vote within {} seconds!",
+                initiator,
+                VOTE_TIMEOUT.as_secs()
+            ));
+            m.components(|c| c.set_action_rows(vec![build_action_row(votes.len(), needed, false)]))
+        })
+        .await
+        .map_err(|e| SunnyError::log(format!("Unable to send vote message: {:?}", e).as_str()))?;
+
+    let mut collector = msg
+        .await_component_interactions(&ctx.shard)
+        .timeout(VOTE_TIMEOUT)
+        .await;
+
+    let mut passed = false;
+    while let Some(mci) = collector.next().await {
+        if mci.data.custom_id != VOTE_ID {
+            continue;
+        }
+
+        // The vote is moot once the song is over
+        if current(ctx, guild_id).await?.0 != Some(track) {
+            break;
+        }
+
+        let listening = count_listeners().await?;
+        if listening.contains(&mci.user.id) {
+            votes.insert(mci.user.id);
+        }
+
+        // Only listeners still in the channel count
+        votes.retain(|v| listening.contains(v));
+        needed = votes_needed(listening.len(), fraction);
+        passed = votes.len() >= needed;
+
+        mci.create_interaction_response(&ctx.http, |cir| {
+            cir.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|m| {
+                    m.components(|c| {
+                        c.set_action_rows(vec![build_action_row(votes.len(), needed, passed)])
+                    })
+                })
+        })
+        .await
+        .map_err(|e| {
+            SunnyError::log(format!("Unable to create interaction response: {:?}", e).as_str())
+        })?;
+
+        if passed {
+            break;
+        }
+    }
+
+    let still_playing = current(ctx, guild_id).await?.0 == Some(track);
+
+    let (result, content) = if !still_playing {
+        (None, "The song already ended")
+    } else if passed {
+        (
+            Some(queue::skip(ctx, guild_id).await?),
+            "Vote passed, song skipped :fast_forward:",
+        )
+    } else {
+        (None, "Not enough votes, the song stays :musical_note:")
+    };
+
+    // Remove the button once the vote is over
+    msg.edit(&ctx.http, |e| {
+        e.content(content);
+        e.components(|c| c)
+    })
+    .await
+    .map_err(|e| SunnyError::log(format!("Unable clear buttons {:?}", e).as_str()))?;
+
+    Ok(result)
+}
+
+/// Skips the current track, or starts a vote to skip it when vote skipping is
+/// on and `user_id` can't skip it outright.
+///
+/// Returns the queue length after skipping, or `None` if the vote failed.
+#[instrument(skip(ctx))]
+pub async fn skip_or_vote(
+    ctx: &Context,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    user_id: UserId,
+) -> SunnyResult<Option<usize>> {
+    let fraction = with_guild_state(ctx, guild_id, |s| s.vote_skip).await?;
+
+    match fraction {
+        Some(fraction) if !checks::can_force_skip(ctx, guild_id, user_id).await? => {
+            vote_skip(ctx, guild_id, channel_id, user_id, fraction).await
+        }
+        _ => queue::skip(ctx, guild_id).await.map(Some),
+    }
+}
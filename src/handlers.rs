@@ -1,13 +1,22 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    env,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 
 use serenity::{async_trait, model::prelude::*, prelude::*};
 
-use songbird::{Event, EventContext, EventHandler as VoiceEventHandler};
+use songbird::{
+    tracks::{LoopState, TrackHandle, TrackQueue},
+    Event, EventContext, EventHandler as VoiceEventHandler,
+};
 use tracing::{event, instrument, Level};
 
-use crate::effects::{self, now_playing};
+use crate::effects::{self, fade, history, library, now_playing, track_error};
 use crate::emit;
-use crate::structs::EventConfig;
+use crate::structs::{with_guild_state, EventConfig, Layer, LayerTrack, SharedAudioSettings};
+
+/// Whether the library was scanned since Sunny started
+static LIBRARY_SCANNED: AtomicBool = AtomicBool::new(false);
 
 pub struct Handler;
 
@@ -22,6 +31,21 @@ impl EventHandler for Handler {
         let status = OnlineStatus::DoNotDisturb;
 
         ctx.set_presence(Some(activity), status).await;
+
+        let res = effects::restore_queues(&ctx).await;
+
+        emit!(res, Level::WARN);
+
+        // Probing every file takes a while, the library fills in as it goes.
+        // Ready fires again on reconnects, those don't need another scan.
+        if env::var("LIBRARY_DIR").is_ok() && !LIBRARY_SCANNED.swap(true, Ordering::SeqCst) {
+            tokio::spawn(async move {
+                match library::scan().await {
+                    Ok(n) => event!(Level::INFO, "Indexed {} library files", n),
+                    Err(e) => event!(Level::WARN, %e, "unable to scan library"),
+                }
+            });
+        }
     }
 }
 
@@ -34,12 +58,150 @@ pub struct TrackPlayNotifier {
 impl VoiceEventHandler for TrackPlayNotifier {
     #[instrument(name = "track_play_notifier_handler")]
     async fn act(&self, event: &EventContext<'_>) -> Option<Event> {
-        if let EventContext::Track(_track) = event {
-            let res =
-                now_playing::send_embed(&self.cfg.ctx, self.cfg.guild_id, self.cfg.text_channel_id)
+        if let EventContext::Track(tracks) = event {
+            let mut songs = 0;
+            for (_state, handle) in tracks.iter() {
+                if is_layer_track(handle).await {
+                    continue;
+                }
+
+                songs += 1;
+                let res = history::record_play(&self.cfg.ctx, self.cfg.guild_id, handle).await;
+
+                emit!(res, Level::WARN);
+
+                let res = now_playing::notify_requester(
+                    &self.cfg.ctx,
+                    self.cfg.guild_id,
+                    self.cfg.voice_channel_id,
+                    handle,
+                )
+                .await;
+
+                emit!(res, Level::WARN);
+            }
+
+            // Ambience and sound effects starting aren't worth an embed
+            if songs > 0 {
+                let res = now_playing::update_embed(
+                    &self.cfg.ctx,
+                    self.cfg.guild_id,
+                    self.cfg.text_channel_id,
+                )
+                .await;
+
+                emit!(res, Level::WARN);
+            }
+        }
+
+        None
+    }
+}
+
+/// Whether a track plays on a mixer layer instead of being a song in the queue
+async fn is_layer_track(handle: &TrackHandle) -> bool {
+    handle.typemap().read().await.contains_key::<LayerTrack>()
+}
+
+#[derive(Debug)]
+pub struct TrackEndNotifier {
+    pub cfg: EventConfig,
+}
+
+#[async_trait]
+impl VoiceEventHandler for TrackEndNotifier {
+    #[instrument(name = "track_end_notifier_handler")]
+    async fn act(&self, event: &EventContext<'_>) -> Option<Event> {
+        if let EventContext::Track(tracks) = event {
+            let mut songs = Vec::new();
+            let mut looped = false;
+            for (state, handle) in tracks.iter() {
+                if is_layer_track(handle).await {
+                    continue;
+                }
+
+                songs.push(handle.uuid());
+                looped |= state.loops != LoopState::Finite(0);
+
+                // songbird has no error event, a stream that dies shows up as
+                // a track ending early
+                let failed = if track_error::died(state, handle).await {
+                    let res = track_error::recover(
+                        &self.cfg.ctx,
+                        self.cfg.guild_id,
+                        self.cfg.text_channel_id,
+                        state,
+                        handle,
+                    )
                     .await;
 
+                    match res {
+                        Ok(retried) => !retried,
+                        Err(e) => {
+                            event!(Level::WARN, %e, "unable to recover track");
+                            true
+                        }
+                    }
+                } else {
+                    false
+                };
+
+                let res = history::record_end(
+                    &self.cfg.ctx,
+                    self.cfg.guild_id,
+                    handle,
+                    state.play_time,
+                    failed,
+                )
+                .await;
+
+                emit!(res, Level::WARN);
+            }
+
+            let res = with_guild_state(&self.cfg.ctx, self.cfg.guild_id, |s| {
+                for (_state, handle) in tracks.iter() {
+                    s.records.remove(&handle.uuid());
+                    s.retried.remove(&handle.uuid());
+
+                    for layer in s.layers.values_mut() {
+                        layer.tracks.retain(|t| t.uuid() != handle.uuid());
+                    }
+                }
+
+                // Looping only applies to the current song, which ended
+                if looped {
+                    s.layer(Layer::Music).looping = false;
+                }
+            })
+            .await;
+
             emit!(res, Level::WARN);
+
+            if songs.is_empty() {
+                return None;
+            }
+
+            // Sunny left, nothing to autoplay into
+            let call_m = songbird::get(&self.cfg.ctx)
+                .await
+                .and_then(|songbird| songbird.get(self.cfg.guild_id))?;
+            let front = call_m.lock().await.queue().current().map(|t| t.uuid());
+
+            // Removed songs and songs crossfaded out end too, autoplay only
+            // has to fill in after the current song, which may have left the
+            // queue already
+            if matches!(front, Some(front) if !songs.contains(&front)) {
+                return None;
+            }
+
+            // Sourcing a track takes a while, so don't hold up other events
+            let ctx = self.cfg.ctx.clone();
+            let guild_id = self.cfg.guild_id;
+            tokio::spawn(async move {
+                let res = effects::autoplay(&ctx, guild_id).await;
+
+                emit!(res, Level::WARN);
+            });
         }
 
         None
@@ -98,3 +260,45 @@ fn check_alone(guild: &Guild, channel_id: ChannelId, bot_id: UserId) -> bool {
         None => false,
     })
 }
+
+/// Per-track handler that starts the crossfade into the next track
+pub struct CrossfadeHandler {
+    pub ctx: Context,
+    pub guild_id: GuildId,
+    pub queue: TrackQueue,
+    pub audio: SharedAudioSettings,
+}
+
+impl std::fmt::Debug for CrossfadeHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CrossfadeHandler")
+            .field("guild_id", &self.guild_id)
+            .field("queue", &self.queue)
+            .field("audio", &self.audio)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl VoiceEventHandler for CrossfadeHandler {
+    async fn act(&self, event: &EventContext<'_>) -> Option<Event> {
+        let crossfade = self.audio.read().ok().and_then(|a| a.crossfade)?;
+
+        if let EventContext::Track(&[(state, handle)]) = event {
+            if fade::crossfade(
+                &self.ctx,
+                self.guild_id,
+                &self.queue,
+                state,
+                handle,
+                crossfade,
+            )
+            .await
+            {
+                return Some(Event::Cancel);
+            }
+        }
+
+        None
+    }
+}
@@ -3,13 +3,14 @@
 
 mod checks;
 mod commands;
+mod db;
 mod effects;
 mod handlers;
 mod hooks;
 mod structs;
 mod utils;
 
-use std::env;
+use std::{env, sync::Arc};
 
 use commands::*;
 use hooks::{after_hook, dispatch_error_hook};
@@ -21,6 +22,7 @@ use serenity::{
     client::Client,
     framework::{standard::macros::group, StandardFramework},
 };
+use structs::GuildStates;
 
 use tokio::select;
 
@@ -30,18 +32,41 @@ use tracing::{event, Level};
 
 #[group]
 #[commands(
+    ambience,
+    announce,
+    autoplay,
+    back,
+    clear_upcoming,
+    crossfade,
+    dedupe,
+    fair,
+    filter,
+    history,
     join,
     leave,
+    library,
+    loudness,
+    mixer,
+    move_track,
+    music,
+    notify,
     pause,
     play,
     play_next,
+    playlist,
     ping,
     resume,
+    sfx,
     remove_at,
+    replay,
+    scene,
     shuffle,
     skip,
+    soundboard,
     stop,
     swap,
+    unshuffle,
+    vote_skip,
     now_playing,
     queue,
     get_group_items,
@@ -77,6 +102,7 @@ async fn main() {
 
     let mut client = init_bot(token, app_id, cmd_prefix).await;
     let shard_manager = client.shard_manager.clone();
+    let data = client.data.clone();
 
     select! {
         res = client.start() => match res {
@@ -92,6 +118,10 @@ async fn main() {
         },
         _ = sigterm.recv() => {
             event!(Level::INFO, "Received SIGTERM, shutting down.");
+            match effects::save_queues(&data).await {
+                Ok(n) => event!(Level::INFO, "Saved {} queues", n),
+                Err(e) => event!(Level::ERROR, %e, "unable to save queues"),
+            }
             shard_manager.lock().await.shutdown_all().await;
         },
     }
@@ -109,6 +139,7 @@ pub async fn init_bot(token: String, app_id: u64, cmd_prefix: String) -> Client
         .event_handler(Handler)
         .framework(framework)
         .register_songbird()
+        .type_map_insert::<GuildStates>(Arc::default())
         .application_id(app_id)
         .await
         .expect("Error creating client")
@@ -1,9 +1,20 @@
-use std::fmt::Debug;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+    time::Duration,
+};
 
+use chrono::{DateTime, Utc};
 use serenity::{
     client::Context,
-    model::id::{ChannelId, GuildId},
+    model::id::{ChannelId, GuildId, MessageId, UserId},
+    prelude::{Mutex, TypeMapKey},
 };
+use songbird::tracks::TrackHandle;
+use uuid::Uuid;
+
+use crate::utils::{SunnyError, SunnyResult};
 
 #[derive(Clone)]
 pub struct EventConfig {
@@ -22,3 +33,430 @@ impl Debug for EventConfig {
             .finish()
     }
 }
+
+/// The voice and text channel a guild's call is bound to.
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelBinding {
+    pub text_channel_id: ChannelId,
+    pub voice_channel_id: ChannelId,
+}
+
+/// The now playing message of a guild, edited in place as tracks change.
+#[derive(Clone, Copy, Debug)]
+pub struct NowPlayingMessage {
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    /// Track the message was sent for
+    pub track: Uuid,
+    /// Track whose cover art is attached to the message, attachments can't be
+    /// replaced by editing
+    pub cover_of: Option<Uuid>,
+}
+
+/// Sunny's own record of who queued a track, when, and through which command.
+#[derive(Clone, Debug)]
+pub struct TrackRecord {
+    pub requester: UserId,
+    pub enqueued_at: DateTime<Utc>,
+    pub command: &'static str,
+    /// Whether the requester was sent a DM that the track started
+    pub notified: bool,
+}
+
+impl TrackRecord {
+    pub fn new(requester: UserId, command: &'static str) -> Self {
+        Self {
+            requester,
+            enqueued_at: Utc::now(),
+            command,
+            notified: false,
+        }
+    }
+
+    /// Record of a track picked by autoplay, Sunny counts as the requester
+    pub fn autoplay(bot_id: UserId) -> Self {
+        Self::new(bot_id, AUTOPLAY_COMMAND)
+    }
+
+    pub fn is_autoplay(&self) -> bool {
+        self.command == AUTOPLAY_COMMAND
+    }
+}
+
+const AUTOPLAY_COMMAND: &str = "autoplay";
+
+/// Where autoplay picks tracks from once the queue runs out.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AutoplaySource {
+    /// Songs played in the guild before
+    History,
+    /// Songs of a saved playlist, `owner` is `None` for the guild's playlists
+    Playlist { owner: Option<UserId>, name: String },
+    /// Files of the local library
+    Library,
+}
+
+/// How the now playing controller announces songs, see `announce`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Announce {
+    /// A new message for every song, earlier ones lose their buttons
+    Every,
+    /// One message, edited as songs change
+    #[default]
+    Live,
+    /// No messages unless someone asks with `now_playing`
+    Silent,
+}
+
+impl Announce {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "every" | "all" => Some(Self::Every),
+            "live" | "one" => Some(Self::Live),
+            "silent" | "off" => Some(Self::Silent),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Every => "every",
+            Self::Live => "live",
+            Self::Silent => "silent",
+        }
+    }
+}
+
+/// ffmpeg audio filter presets for `filter`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterPreset {
+    BassBoost,
+    Nightcore,
+    Vaporwave,
+    Muffled,
+    Echo,
+    Normalize,
+    EightD,
+}
+
+impl FilterPreset {
+    pub const ALL: [Self; 7] = [
+        Self::BassBoost,
+        Self::Nightcore,
+        Self::Vaporwave,
+        Self::Muffled,
+        Self::Echo,
+        Self::Normalize,
+        Self::EightD,
+    ];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "bassboost" | "bass" => Some(Self::BassBoost),
+            "nightcore" => Some(Self::Nightcore),
+            "vaporwave" => Some(Self::Vaporwave),
+            "muffled" | "lowpass" => Some(Self::Muffled),
+            "echo" => Some(Self::Echo),
+            "normalize" | "normalise" => Some(Self::Normalize),
+            "8d" => Some(Self::EightD),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::BassBoost => "bassboost",
+            Self::Nightcore => "nightcore",
+            Self::Vaporwave => "vaporwave",
+            Self::Muffled => "muffled",
+            Self::Echo => "echo",
+            Self::Normalize => "normalize",
+            Self::EightD => "8d",
+        }
+    }
+
+    /// The ffmpeg `-af` filter chain of the preset
+    pub const fn ffmpeg_filter(self) -> &'static str {
+        match self {
+            Self::BassBoost => "bass=g=10",
+            Self::Nightcore => "aresample=48000,asetrate=48000*1.25,aresample=48000",
+            Self::Vaporwave => "aresample=48000,asetrate=48000*0.8,aresample=48000",
+            // Sounds like it's coming from the next room
+            Self::Muffled => "lowpass=f=400",
+            Self::Echo => "aecho=0.8:0.88:60:0.4",
+            Self::Normalize => "dynaudnorm",
+            Self::EightD => "apulsator=hz=0.08",
+        }
+    }
+
+    /// How much faster than its source a song plays with the preset
+    pub const fn tempo(self) -> f64 {
+        match self {
+            Self::Nightcore => 1.25,
+            Self::Vaporwave => 0.8,
+            _ => 1.0,
+        }
+    }
+}
+
+/// Audio settings applied whenever a track's source is (re)created, so
+/// queued tracks pick up changes too
+#[derive(Clone, Debug, Default)]
+pub struct AudioSettings {
+    pub filter: Option<FilterPreset>,
+    /// Integrated loudness (LUFS) tracks are normalized to, `None` leaves them as is
+    pub loudness_target: Option<f64>,
+    /// How long the end of a track overlaps the start of the next one, also
+    /// used for fading out on `stop`, `skip` and `leave`
+    pub crossfade: Option<Duration>,
+}
+
+impl AudioSettings {
+    /// How much faster than their sources songs play
+    pub fn tempo(&self) -> f64 {
+        self.filter.map_or(1.0, FilterPreset::tempo)
+    }
+}
+
+/// [`AudioSettings`] shared between a guild's state and its track sources
+pub type SharedAudioSettings = Arc<std::sync::RwLock<AudioSettings>>;
+
+/// Songs still fading out after the queue moved on from them
+pub type FadingTracks = Arc<std::sync::Mutex<Vec<TrackHandle>>>;
+
+/// Maps the position of a track, which runs at the tempo of its filter, to
+/// the time in its source. Anchored where the source was last (re)created.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SourceClock {
+    pub position: Duration,
+    pub source: Duration,
+    pub tempo: f64,
+}
+
+impl SourceClock {
+    /// Clock of a source that starts at its beginning
+    pub const fn new(tempo: f64) -> Self {
+        Self {
+            position: Duration::ZERO,
+            source: Duration::ZERO,
+            tempo,
+        }
+    }
+
+    /// Time in the source at `position`
+    pub fn source_time(&self, position: Duration) -> Duration {
+        if position >= self.position {
+            self.source + (position - self.position).mul_f64(self.tempo)
+        } else {
+            self.source
+                .saturating_sub((self.position - position).mul_f64(self.tempo))
+        }
+    }
+
+    /// Position at which the source reaches `source`
+    pub fn position_at(&self, source: Duration) -> Duration {
+        if source >= self.source {
+            self.position + (source - self.source).div_f64(self.tempo)
+        } else {
+            self.position
+                .saturating_sub((self.source - source).div_f64(self.tempo))
+        }
+    }
+}
+
+/// [`SourceClock`] shared between a track's source and its typemap
+pub type SharedClock = Arc<std::sync::Mutex<SourceClock>>;
+
+/// Layers of the mixer, the queue is the music layer and the others play
+/// alongside it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Layer {
+    Music,
+    Ambience,
+    Sfx,
+}
+
+impl Layer {
+    pub const ALL: [Self; 3] = [Self::Music, Self::Ambience, Self::Sfx];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Music => "music",
+            Self::Ambience => "ambience",
+            Self::Sfx => "sfx",
+        }
+    }
+}
+
+/// Volume, loop flag and playing tracks of a mixer layer
+#[derive(Clone, Debug)]
+pub struct LayerState {
+    /// Volume of the layer, 1.0 is the volume of the source
+    pub volume: f32,
+    /// Whether the tracks of the layer loop, for music just the current song
+    pub looping: bool,
+    /// Tracks playing on the layer, the music layer uses the queue instead
+    pub tracks: Vec<TrackHandle>,
+}
+
+impl LayerState {
+    fn new(layer: Layer) -> Self {
+        Self {
+            volume: 1.0,
+            looping: layer == Layer::Ambience,
+            tracks: Vec::new(),
+        }
+    }
+}
+
+/// Per-guild state Sunny keeps on top of songbird's call and queue.
+#[derive(Debug, Default)]
+pub struct GuildState {
+    pub binding: Option<ChannelBinding>,
+    /// Records of queued tracks, keyed by their [`TrackHandle`](songbird::tracks::TrackHandle) uuid
+    pub records: HashMap<Uuid, TrackRecord>,
+    /// Whether new tracks take turns per requester instead of being appended
+    pub fair_queue: bool,
+    /// Fraction of listeners needed to skip a song, `None` skips instantly
+    pub vote_skip: Option<f32>,
+    /// Play history row ids of tracks that started playing
+    pub history_ids: HashMap<Uuid, i32>,
+    /// Tracks that are already a retry of a track whose stream died
+    pub retried: HashSet<Uuid>,
+    /// Where tracks come from when the queue empties, `None` if autoplay is off
+    pub autoplay: Option<AutoplaySource>,
+    /// Audio settings shared with the sources of the guild's tracks
+    pub audio: SharedAudioSettings,
+    /// Mixer layers that were changed from their defaults or played on
+    pub layers: HashMap<Layer, LayerState>,
+    /// Soundboard clips playing, the music stays ducked until they're done
+    pub ducking: usize,
+    /// Fraction of its volume the music is ducked to, `None` if it isn't
+    pub duck: Option<f32>,
+    /// The now playing controller, if one is up
+    pub now_playing: Option<NowPlayingMessage>,
+    /// How songs are announced
+    pub announce: Announce,
+    /// Users who get a DM when a song they queued starts
+    pub start_dms: HashSet<UserId>,
+    /// Order of the upcoming tracks before the queue was shuffled, `None` if
+    /// it isn't shuffled
+    pub unshuffled: Option<Vec<Uuid>>,
+    /// Whether new tracks go to a random upcoming position
+    pub continuous_shuffle: bool,
+    /// Songs crossfading out, they left the queue already
+    pub fading_out: FadingTracks,
+}
+
+impl GuildState {
+    /// Gets the state of a mixer layer
+    pub fn layer(&mut self, layer: Layer) -> &mut LayerState {
+        self.layers
+            .entry(layer)
+            .or_insert_with(|| LayerState::new(layer))
+    }
+
+    /// Volume songs play at, the music layer's volume ducked under clips
+    pub fn music_volume(&mut self) -> f32 {
+        self.layer(Layer::Music).volume * self.duck.unwrap_or(1.0)
+    }
+}
+
+/// [`TypeMapKey`] for the embedded cover art of a track, kept in its
+/// [`TrackHandle`](songbird::tracks::TrackHandle) typemap.
+pub struct CoverArt;
+
+impl TypeMapKey for CoverArt {
+    type Value = Arc<Vec<u8>>;
+}
+
+/// [`TypeMapKey`] for the [`SourceClock`] of a queued track
+pub struct TrackClock;
+
+impl TypeMapKey for TrackClock {
+    type Value = SharedClock;
+}
+
+/// [`TypeMapKey`] marking a track that's fading out, as far as listeners
+/// are concerned the track after it is playing already.
+pub struct FadingOut;
+
+impl TypeMapKey for FadingOut {
+    type Value = ();
+}
+
+/// [`TypeMapKey`] for the mixer layer of a track that isn't in the queue,
+/// the queue's event handlers leave those alone.
+pub struct LayerTrack;
+
+impl TypeMapKey for LayerTrack {
+    type Value = Layer;
+}
+
+/// [`TypeMapKey`] for the state of every guild Sunny is active in.
+pub struct GuildStates;
+
+impl TypeMapKey for GuildStates {
+    type Value = Arc<Mutex<HashMap<GuildId, GuildState>>>;
+}
+
+/// Runs `f` on the [`GuildState`] of `guild_id`, creating it if needed.
+pub async fn with_guild_state<F, O>(ctx: &Context, guild_id: GuildId, f: F) -> SunnyResult<O>
+where
+    F: FnOnce(&mut GuildState) -> O,
+{
+    let states = ctx
+        .data
+        .read()
+        .await
+        .get::<GuildStates>()
+        .cloned()
+        .ok_or_else(|| SunnyError::log("Couldn't get guild states"))?;
+
+    let mut states = states.lock().await;
+
+    Ok(f(states.entry(guild_id).or_default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn nightcore_plays_faster_than_the_source() {
+        let clock = SourceClock::new(FilterPreset::Nightcore.tempo());
+
+        assert_eq!(clock.source_time(4 * MINUTE), 5 * MINUTE);
+        assert_eq!(clock.position_at(5 * MINUTE), 4 * MINUTE);
+    }
+
+    #[test]
+    fn clock_continues_from_its_anchor() {
+        // One minute in at normal speed, then switched to vaporwave
+        let clock = SourceClock {
+            position: MINUTE,
+            source: MINUTE,
+            tempo: FilterPreset::Vaporwave.tempo(),
+        };
+
+        assert_eq!(clock.source_time(MINUTE * 2), MINUTE + MINUTE.mul_f64(0.8));
+        assert_eq!(
+            clock.position_at(MINUTE * 3),
+            MINUTE + (MINUTE * 2).div_f64(0.8)
+        );
+        assert_eq!(
+            clock.source_time(Duration::ZERO),
+            MINUTE - MINUTE.mul_f64(0.8)
+        );
+    }
+
+    #[test]
+    fn presets_without_tempo_change_keep_time() {
+        let clock = SourceClock::new(FilterPreset::BassBoost.tempo());
+
+        assert_eq!(clock.source_time(MINUTE), MINUTE);
+        assert_eq!(clock.position_at(MINUTE), MINUTE);
+    }
+}
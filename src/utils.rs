@@ -26,12 +26,20 @@ impl SunnyError {
             log: log.to_string(),
         }
     }
+
+    /// The part of the error meant for the user, if there is one
+    pub fn user_message(&self) -> Option<&str> {
+        match self {
+            Self::User(user) | Self::UserAndLog { user, .. } => Some(user),
+            Self::Log(_) => None,
+        }
+    }
 }
 
 #[macro_export]
 macro_rules! sunny_log {
     ($err:expr, $ctx:expr, $msg:expr, $lvl:expr) => {
-        use crate::emit;
+        use $crate::emit;
 
         let error: &SunnyError = $err;
         let ctx: &Context = $ctx;